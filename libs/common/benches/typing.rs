@@ -0,0 +1,42 @@
+//	Types a paragraph one character at a time and prints the piece count
+//	and the insert latency. Run with `cargo bench -p common`.
+//
+//	"sequential" types each character after the previous one, which
+//	extends the last piece. "scattered" moves the cursor before every
+//	character, so each insert still needs a piece of its own, like every
+//	insert did before sequential typing was detected.
+use std::time::{Duration, Instant};
+
+use common::piecetable::PieceTable;
+
+const PARAGRAPH: &str = "The quick brown fox jumps over the lazy dog. ";
+const KEYSTROKES: usize = 20_000;
+
+fn type_text<F>(name: &str, mut cursor: F)
+where
+    F: FnMut(usize, usize) -> usize,
+{
+    let mut pt = PieceTable::new(&"-".repeat(1000));
+    let mut pos = 0;
+    let mut total = Duration::ZERO;
+    for (i, c) in PARAGRAPH.chars().cycle().take(KEYSTROKES).enumerate() {
+        pos = cursor(i, pos).min(pt.text_len());
+        let txt = c.to_string();
+        let start = Instant::now();
+        pt.insert(&txt, pos);
+        total += start.elapsed();
+        pos += txt.len();
+    }
+    println!(
+        "{:<12} {:>8} pieces {:>10.2?} per insert",
+        name,
+        pt.len(),
+        total / KEYSTROKES as u32
+    );
+}
+
+fn main() {
+    println!("{} keystrokes", KEYSTROKES);
+    type_text("sequential", |_, pos| pos);
+    type_text("scattered", |i, _| (i * 7919) % 1000);
+}
@@ -0,0 +1,201 @@
+use std::{
+    fs::File,
+    ops::{Index, Range},
+    str,
+    sync::{Arc, OnceLock},
+};
+
+use memmap2::Mmap;
+
+use crate::Result;
+
+//	Text that pieces point into. Reading a range gives a `&str` like
+//	indexing a `String`.
+#[derive(Clone, Debug)]
+pub enum Buffer {
+    Owned(String),
+    Mapped(MappedChunk),
+}
+
+impl Buffer {
+    //	Buffer of a range of a mapped file. The range must start and end
+    //	on characters if the file is valid UTF-8.
+    pub fn mapped(text: Arc<MappedText>, range: Range<usize>) -> Self {
+        Buffer::Mapped(MappedChunk {
+            text,
+            range,
+            checked: OnceLock::new(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Buffer::Owned(txt) => txt.len(),
+            Buffer::Mapped(chunk) => chunk.range.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //	Bytes of the text. Validates a mapped buffer first.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.as_str().as_bytes()
+    }
+
+    pub fn is_char_boundary(&self, index: usize) -> bool {
+        self.as_str().is_char_boundary(index)
+    }
+
+    //	Whether bytes of a mapped buffer were not UTF-8 and read as '?'.
+    //	Validates the buffer if it was not read yet.
+    pub fn is_lossy(&self) -> bool {
+        match self {
+            Buffer::Owned(_) => false,
+            Buffer::Mapped(chunk) => chunk.repaired().is_some(),
+        }
+    }
+
+    //	Appends text to an owned buffer.
+    //
+    //	Panics on a mapped buffer, which is read-only.
+    pub fn push_str(&mut self, txt: &str) {
+        match self {
+            Buffer::Owned(buffer) => buffer.push_str(txt),
+            Buffer::Mapped(_) => panic!("Mapped buffers are read-only."),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Buffer::Owned(txt) => txt,
+            Buffer::Mapped(chunk) => chunk.as_str(),
+        }
+    }
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Buffer::Owned(String::new())
+    }
+}
+
+impl From<String> for Buffer {
+    fn from(txt: String) -> Self {
+        Buffer::Owned(txt)
+    }
+}
+
+impl Index<Range<usize>> for Buffer {
+    type Output = str;
+
+    //	Panics if the range is out of bounds or splits a character.
+    fn index(&self, range: Range<usize>) -> &str {
+        &self.as_str()[range]
+    }
+}
+
+//	A read-only file map shared by the chunks of the file and with
+//	snapshots, which keep it alive.
+pub struct MappedText {
+    map: Mmap,
+}
+
+impl MappedText {
+    //	Maps a file. The file must not be truncated or changed by other
+    //	programs while it is mapped.
+    pub fn open(file: &File) -> Result<Self> {
+        //	SAFETY: the map is only read, and the caller keeps the file
+        //	unchanged while the map is alive.
+        let map = unsafe { Mmap::map(file)? };
+        Ok(Self { map })
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    //	Raw bytes of the file, which may not be UTF-8.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    //	Whether an index is not inside a UTF-8 sequence, judged by the
+    //	byte at the index alone.
+    pub fn is_char_boundary(&self, index: usize) -> bool {
+        match self.map.get(index) {
+            Some(&b) => b & 0xC0 != 0x80,
+            None => index == self.map.len(),
+        }
+    }
+}
+
+impl std::fmt::Debug for MappedText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedText")
+            .field("len", &self.map.len())
+            .finish_non_exhaustive()
+    }
+}
+
+//	A chunk of a mapped file. Its UTF-8 is validated the first time it
+//	is read, so opening does not touch the file.
+#[derive(Clone, Debug)]
+pub struct MappedChunk {
+    text: Arc<MappedText>,
+    range: Range<usize>,
+    //	Set once the chunk is validated. Holds a copy of the chunk with
+    //	every byte of an invalid sequence read as '?', which keeps every
+    //	offset the same. Only this chunk is copied.
+    checked: OnceLock<Option<Box<str>>>,
+}
+
+impl MappedChunk {
+    fn as_str(&self) -> &str {
+        if let Some(repaired) = self.repaired() {
+            return repaired;
+        }
+        //	SAFETY: the chunk was just validated as UTF-8.
+        unsafe { str::from_utf8_unchecked(self.bytes()) }
+    }
+
+    fn repaired(&self) -> Option<&str> {
+        self.checked
+            .get_or_init(|| match str::from_utf8(self.bytes()) {
+                Ok(_) => None,
+                Err(_) => Some(repair(self.bytes())),
+            })
+            .as_deref()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.text.map[self.range.clone()]
+    }
+}
+
+//	Copies the bytes, replacing every byte of an invalid sequence with
+//	'?' so the length stays the same.
+fn repair(mut bytes: &[u8]) -> Box<str> {
+    let mut txt = String::with_capacity(bytes.len());
+    loop {
+        match str::from_utf8(bytes) {
+            Ok(valid) => {
+                txt.push_str(valid);
+                return txt.into_boxed_str();
+            }
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                //	SAFETY: `valid_up_to` bytes were just validated.
+                txt.push_str(unsafe { str::from_utf8_unchecked(valid) });
+                let invalid = err.error_len().unwrap_or(rest.len());
+                txt.extend(std::iter::repeat_n('?', invalid));
+                bytes = &rest[invalid..];
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    io::{self, Read},
+    str,
+};
+
+use encoding_rs::{CoderResult, Decoder, UTF_16BE, UTF_16LE, UTF_8};
+
+use crate::{
+    encoding::{self, Encoding},
+    piecetable::PieceTable,
+    piecetree::{create_line_starts, LineStarts, AVERAGE_BUFFER_SIZE},
+    Result,
+};
+
+//	Bytes held back to detect the encoding of the input.
+const DETECTION_LEN: usize = 4096;
+
+//	Bytes read from a reader at once.
+const READ_LEN: usize = 64 * 1024;
+
+//	Builds a piece table from input that arrives in chunks of bytes, like
+//	a pipe, a socket or a decompressor. The text is decoded and split
+//	into buffers with their line starts as the bytes come in, so it is
+//	never held in one allocation.
+//
+//	The encoding is picked like `PieceTable::from_file` does. Input with
+//	a byte order mark or that looks like UTF-16 is decoded in that
+//	encoding, with malformed sequences replaced by U+FFFD. That makes the
+//	table lossy, so it is not written back. Other input is read as UTF-8
+//	as long as it is valid. Once it is not, the text read
+//	so far is decoded again as Latin-1, or as Windows-1252 if it has
+//	bytes in 0x80..=0x9F, so legacy files are written back unchanged.
+#[derive(Default)]
+pub struct PieceTableBuilder {
+    //	First bytes of the input until the encoding is detected.
+    head: Vec<u8>,
+    decoding: Option<Decoding>,
+    encoding: Encoding,
+    bom: bool,
+    //	Whether malformed sequences were replaced with U+FFFD.
+    lossy: bool,
+    //	Chunk that is filled next.
+    chunk: String,
+    chunks: Vec<String>,
+    //	Line starts and line ending counts of each chunk.
+    line_starts: Vec<LineStarts>,
+    //	Reused for the text of each decoded batch of bytes.
+    decoded: String,
+}
+
+//	How the input is decoded once its first bytes are seen.
+enum Decoding {
+    //	Checked to be UTF-8 while it lasts. The first bytes of a character
+    //	wait for the rest of it.
+    Utf8 { pending: Vec<u8> },
+    //	Every byte is a character of Latin-1 or Windows-1252.
+    SingleByte,
+    //	UTF-16, or UTF-8 after a byte order mark.
+    Decoder(Decoder),
+}
+
+impl PieceTableBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //	Adds the next bytes of the input. A character may be split
+    //	between two calls.
+    pub fn accept_chunk(&mut self, bytes: &[u8]) {
+        if self.decoding.is_some() {
+            self.decode(bytes, false);
+            return;
+        }
+        self.head.extend_from_slice(bytes);
+        if self.head.len() >= DETECTION_LEN {
+            self.detect();
+        }
+    }
+
+    //	Adds everything a reader returns until it ends.
+    pub fn read_from<R: Read>(&mut self, mut reader: R) -> Result<()> {
+        let mut bytes = vec![0; READ_LEN];
+        loop {
+            match reader.read(&mut bytes) {
+                Ok(0) => return Ok(()),
+                Ok(n) => self.accept_chunk(&bytes[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    //	Builds the table from the input added so far. UTF-8 input that
+    //	ends inside a character is read as a single byte encoding.
+    pub fn finish(mut self) -> PieceTable {
+        if self.decoding.is_none() {
+            self.detect();
+        }
+        self.decode(&[], true);
+        if !self.chunk.is_empty() {
+            self.seal_chunk();
+        }
+        PieceTable::from_loaded(
+            self.chunks,
+            self.line_starts,
+            self.encoding,
+            self.bom,
+            self.lossy,
+        )
+    }
+
+    //	Picks the decoding from the first bytes and decodes them.
+    fn detect(&mut self) {
+        let (encoding, bom) = encoding::detect_stream(&self.head);
+        self.encoding = encoding;
+        self.bom = bom;
+        self.decoding = Some(match encoding {
+            Encoding::Utf16Le => Decoding::Decoder(UTF_16LE.new_decoder_without_bom_handling()),
+            Encoding::Utf16Be => Decoding::Decoder(UTF_16BE.new_decoder_without_bom_handling()),
+            _ if bom => Decoding::Decoder(UTF_8.new_decoder_without_bom_handling()),
+            _ => Decoding::Utf8 {
+                pending: Vec::new(),
+            },
+        });
+
+        let head = std::mem::take(&mut self.head);
+        let bom_len = if bom { encoding.bom().len() } else { 0 };
+        self.decode(&head[bom_len..], false);
+    }
+
+    fn decode(&mut self, bytes: &[u8], last: bool) {
+        let decoding = match self.decoding.take() {
+            Some(Decoding::Utf8 { mut pending }) => {
+                if self.decode_utf8(&mut pending, bytes, last) {
+                    Decoding::Utf8 { pending }
+                } else {
+                    self.fall_back_to_single_byte(&pending, bytes);
+                    Decoding::SingleByte
+                }
+            }
+            Some(Decoding::SingleByte) => {
+                self.decode_single_byte(bytes);
+                Decoding::SingleByte
+            }
+            Some(Decoding::Decoder(mut decoder)) => {
+                self.decode_with(&mut decoder, bytes, last);
+                Decoding::Decoder(decoder)
+            }
+            None => return,
+        };
+        self.decoding = Some(decoding);
+    }
+
+    //	Moves UTF-8 into chunks and keeps the start of a character that
+    //	is not complete yet. Returns false without moving anything if the
+    //	bytes are not UTF-8.
+    fn decode_utf8(&mut self, pending: &mut Vec<u8>, mut bytes: &[u8], last: bool) -> bool {
+        let mut head = Vec::new();
+        if let Some(&lead) = pending.first() {
+            let missing = utf8_len(lead) - pending.len();
+            let take = missing.min(bytes.len());
+            head.extend_from_slice(pending);
+            head.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+            match str::from_utf8(&head) {
+                Ok(_) => {}
+                Err(err) if err.error_len().is_none() && !last => {
+                    *pending = head;
+                    return true;
+                }
+                Err(_) => return false,
+            }
+        }
+        let (valid, rest) = match str::from_utf8(bytes) {
+            Ok(txt) => (txt, &[][..]),
+            Err(err) if err.error_len().is_none() && !last => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                //	SAFETY: `valid_up_to` bytes were just validated.
+                (unsafe { str::from_utf8_unchecked(valid) }, rest)
+            }
+            Err(_) => return false,
+        };
+        //	SAFETY: the head is empty or was validated above.
+        self.push_text(unsafe { str::from_utf8_unchecked(&head) });
+        self.push_text(valid);
+        *pending = rest.to_vec();
+        true
+    }
+
+    //	Decodes the text read so far again, byte by byte, followed by the
+    //	bytes that were not UTF-8. The chunks hold the bytes as they were
+    //	read since they were valid UTF-8.
+    fn fall_back_to_single_byte(&mut self, pending: &[u8], bytes: &[u8]) {
+        self.encoding = Encoding::Latin1;
+        let chunks = std::mem::take(&mut self.chunks);
+        let chunk = std::mem::take(&mut self.chunk);
+        self.line_starts.clear();
+        for txt in chunks.into_iter().chain([chunk]) {
+            self.decode_single_byte(txt.as_bytes());
+        }
+        self.decode_single_byte(pending);
+        self.decode_single_byte(bytes);
+    }
+
+    //	Latin-1 and Windows-1252 only differ in 0x80..=0x9F, so the text
+    //	before the first of those bytes reads the same in both.
+    fn decode_single_byte(&mut self, bytes: &[u8]) {
+        if bytes.iter().any(|b| (0x80..=0x9F).contains(b)) {
+            self.encoding = Encoding::Windows1252;
+        }
+        let (txt, _) = encoding::decode_as(bytes, Encoding::Windows1252);
+        self.push_text(&txt);
+    }
+
+    //	Decodes bytes and moves the text into chunks. The decoder keeps
+    //	the bytes of a character that is not complete yet.
+    fn decode_with(&mut self, decoder: &mut Decoder, mut bytes: &[u8], last: bool) {
+        let mut decoded = std::mem::take(&mut self.decoded);
+        loop {
+            decoded.clear();
+            let needed = decoder.max_utf8_buffer_length(bytes.len());
+            decoded.reserve(needed.unwrap_or(READ_LEN).min(READ_LEN * 4));
+            let (result, read, replaced) = decoder.decode_to_string(bytes, &mut decoded, last);
+            self.lossy |= replaced;
+            bytes = &bytes[read..];
+            self.push_text(&decoded);
+            if result == CoderResult::InputEmpty {
+                break;
+            }
+        }
+        self.decoded = decoded;
+    }
+
+    //	Appends text to the current chunk and starts a new chunk once it
+    //	is full. Chunks end on character boundaries.
+    fn push_text(&mut self, mut txt: &str) {
+        while !txt.is_empty() {
+            if self.chunk.capacity() == 0 {
+                self.chunk.reserve_exact(AVERAGE_BUFFER_SIZE);
+            }
+            let mut end = (AVERAGE_BUFFER_SIZE - self.chunk.len()).min(txt.len());
+            while !txt.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.chunk.push_str(&txt[..end]);
+            txt = &txt[end..];
+            if !txt.is_empty() || self.chunk.len() == AVERAGE_BUFFER_SIZE {
+                self.seal_chunk();
+            }
+        }
+    }
+
+    //	Finishes the current chunk and finds its line starts.
+    fn seal_chunk(&mut self) {
+        let mut chunk = std::mem::take(&mut self.chunk);
+        chunk.shrink_to_fit();
+        self.line_starts
+            .push(create_line_starts(Vec::new(), &chunk));
+        self.chunks.push(chunk);
+    }
+}
+
+//	Length of a UTF-8 sequence from its first byte.
+fn utf8_len(lead: u8) -> usize {
+    match lead {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        _ => 4,
+    }
+}
@@ -0,0 +1,42 @@
+//	When a piece table compacts its pieces on its own. Compaction never
+//	changes the text and keeps every undo step valid.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CompactionPolicy {
+    //	Compact once the table has more pieces than this.
+    pub max_pieces: Option<usize>,
+    //	Rebuild the buffers once this share of the add buffer is no
+    //	longer part of the text. Only done while there is no undo
+    //	history that could still point into the add buffer.
+    pub max_garbage_ratio: Option<f64>,
+    //	Runs of pieces shorter than this are copied into a single piece.
+    pub small_piece_len: usize,
+}
+
+impl CompactionPolicy {
+    //	Never compacts on its own.
+    pub const DISABLED: Self = Self {
+        max_pieces: None,
+        max_garbage_ratio: None,
+        small_piece_len: 64,
+    };
+
+    pub fn by_piece_count(max_pieces: usize) -> Self {
+        Self {
+            max_pieces: Some(max_pieces),
+            ..Self::DISABLED
+        }
+    }
+
+    pub fn by_garbage_ratio(max_garbage_ratio: f64) -> Self {
+        Self {
+            max_garbage_ratio: Some(max_garbage_ratio),
+            ..Self::DISABLED
+        }
+    }
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self::DISABLED
+    }
+}
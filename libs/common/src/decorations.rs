@@ -0,0 +1,460 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{events::Change, markers::Stickiness};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct DecorationId(u64);
+
+//	Range of the text with a class the UI renders it with, like a search
+//	highlight or a lint squiggle.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Decoration {
+    pub id: DecorationId,
+    pub range: Range<usize>,
+    pub class: String,
+}
+
+//	How the edges of a decoration move when text is inserted at them.
+//	A start that sticks left and an end that sticks right grow with text
+//	typed at the edges.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RangeStickiness {
+    pub start: Stickiness,
+    pub end: Stickiness,
+}
+
+impl RangeStickiness {
+    pub const NEVER_GROWS: Self = Self {
+        start: Stickiness::Right,
+        end: Stickiness::Left,
+    };
+    pub const ALWAYS_GROWS: Self = Self {
+        start: Stickiness::Left,
+        end: Stickiness::Right,
+    };
+}
+
+impl Default for RangeStickiness {
+    fn default() -> Self {
+        Self::NEVER_GROWS
+    }
+}
+
+type Link = usize;
+
+//	Node 0 is the empty tree.
+const SENTINEL: Link = 0;
+
+#[derive(Clone, Debug)]
+struct IntervalNode {
+    id: DecorationId,
+    class: String,
+    stickiness: RangeStickiness,
+    //	Start, end and max end are without the pending shifts of the
+    //	ancestors.
+    start: usize,
+    end: usize,
+    //	Largest end in the subtree.
+    max_end: usize,
+    //	Shift not yet applied to the children.
+    lazy: isize,
+    priority: u64,
+    parent: Link,
+    left: Link,
+    right: Link,
+}
+
+//	Decorations kept in a treap ordered by start, where every node knows
+//	the largest end below it. Finding the decorations of a range visits
+//	O(log n + k) nodes and edits shift the following decorations lazily.
+pub struct IntervalTree {
+    nodes: Vec<IntervalNode>,
+    free: Vec<Link>,
+    root: Link,
+    ids: HashMap<DecorationId, Link>,
+    next_id: u64,
+    //	State of the generator for node priorities.
+    seed: u64,
+}
+
+impl Default for IntervalTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IntervalTree {
+    pub fn new() -> Self {
+        let sentinel = IntervalNode {
+            id: DecorationId(u64::MAX),
+            class: String::new(),
+            stickiness: RangeStickiness::default(),
+            start: 0,
+            end: 0,
+            max_end: 0,
+            lazy: 0,
+            priority: 0,
+            parent: SENTINEL,
+            left: SENTINEL,
+            right: SENTINEL,
+        };
+        Self {
+            nodes: vec![sentinel],
+            free: Vec::new(),
+            root: SENTINEL,
+            ids: HashMap::new(),
+            next_id: 0,
+            seed: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn add(
+        &mut self,
+        range: Range<usize>,
+        class: &str,
+        stickiness: RangeStickiness,
+    ) -> DecorationId {
+        let id = DecorationId(self.next_id);
+        self.next_id += 1;
+        let priority = self.next_priority();
+        let node = self.alloc(IntervalNode {
+            id,
+            class: class.to_string(),
+            stickiness,
+            start: range.start,
+            end: range.end.max(range.start),
+            max_end: range.end.max(range.start),
+            lazy: 0,
+            priority,
+            parent: SENTINEL,
+            left: SENTINEL,
+            right: SENTINEL,
+        });
+        self.ids.insert(id, node);
+
+        let (l, r) = self.split(self.root, range.start);
+        let l = self.merge(l, node);
+        self.root = self.merge(l, r);
+        self.nodes[self.root].parent = SENTINEL;
+        id
+    }
+
+    //	Returns false if there is no decoration with the id.
+    pub fn remove(&mut self, id: DecorationId) -> bool {
+        let Some(node) = self.ids.remove(&id) else {
+            return false;
+        };
+        self.push(node);
+        let IntervalNode {
+            parent,
+            left,
+            right,
+            ..
+        } = self.nodes[node];
+        let merged = self.merge(left, right);
+        if parent == SENTINEL {
+            self.root = merged;
+            self.nodes[merged].parent = SENTINEL;
+        } else {
+            if self.nodes[parent].left == node {
+                self.set_left(parent, merged);
+            } else {
+                self.set_right(parent, merged);
+            }
+            //	The ancestors may have lost their largest end.
+            let mut ancestor = parent;
+            while ancestor != SENTINEL {
+                self.update(ancestor);
+                ancestor = self.nodes[ancestor].parent;
+            }
+        }
+        self.nodes[node].class = String::new();
+        self.free.push(node);
+        true
+    }
+
+    pub fn get(&self, id: DecorationId) -> Option<Decoration> {
+        let node = *self.ids.get(&id)?;
+        let mut shift = 0;
+        let mut parent = self.nodes[node].parent;
+        while parent != SENTINEL {
+            shift += self.nodes[parent].lazy;
+            parent = self.nodes[parent].parent;
+        }
+        Some(self.decoration(node, shift))
+    }
+
+    //	Decorations that overlap or touch the range, ordered by start.
+    pub fn search(&self, range: Range<usize>) -> Vec<Decoration> {
+        let mut ret = Vec::new();
+        self.collect_range(self.root, 0, &range, &mut ret);
+        ret
+    }
+
+    //	Moves the decorations for a change. Edges inside the replaced
+    //	range move to the edge of the replacement closest to the rest of
+    //	the decoration.
+    pub fn apply(&mut self, change: &Change) {
+        if self.root == SENTINEL {
+            return;
+        }
+        let (start, end) = (change.range.start, change.range.end);
+        let inserted = change.text.len();
+        let delta = inserted as isize - change.range.len() as isize;
+
+        //	Decorations starting before the change only need their end
+        //	moved. Those starting inside it are taken out and put back.
+        let (before, rest) = self.split(self.root, start);
+        let (touched, after) = self.split(rest, end + 1);
+        if after != SENTINEL {
+            self.shift(after, delta);
+        }
+        self.move_ends(before, change);
+
+        let mut nodes = Vec::new();
+        self.collect_nodes(touched, &mut nodes);
+        for &node in nodes.iter() {
+            let n = &self.nodes[node];
+            let new_start = move_edge(n.start, n.stickiness.start, true, change);
+            let new_end = move_edge(n.end, n.stickiness.end, false, change);
+            let n = &mut self.nodes[node];
+            n.start = new_start;
+            n.end = new_end.max(new_start);
+            n.max_end = n.end;
+            n.left = SENTINEL;
+            n.right = SENTINEL;
+        }
+        //	Every start is now either at the start of the change or after
+        //	the inserted text.
+        nodes.sort_by_key(|&n| self.nodes[n].start);
+        let touched = nodes
+            .into_iter()
+            .fold(SENTINEL, |tree, node| self.merge(tree, node));
+
+        let before = self.merge(before, touched);
+        self.root = self.merge(before, after);
+        self.nodes[self.root].parent = SENTINEL;
+    }
+
+    fn decoration(&self, node: Link, shift: isize) -> Decoration {
+        let n = &self.nodes[node];
+        Decoration {
+            id: n.id,
+            range: n.start.wrapping_add_signed(shift)..n.end.wrapping_add_signed(shift),
+            class: n.class.clone(),
+        }
+    }
+
+    //	Moves the ends of the decorations of a subtree that reach into
+    //	the change. Every start of the subtree is before the change.
+    fn move_ends(&mut self, node: Link, change: &Change) {
+        if node == SENTINEL || self.nodes[node].max_end < change.range.start {
+            return;
+        }
+        self.push(node);
+        let IntervalNode {
+            left,
+            right,
+            end,
+            stickiness,
+            ..
+        } = self.nodes[node];
+        self.move_ends(left, change);
+        self.move_ends(right, change);
+        if end >= change.range.start {
+            self.nodes[node].end = move_edge(end, stickiness.end, false, change);
+        }
+        self.update(node);
+    }
+
+    fn next_priority(&mut self) -> u64 {
+        //	xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    fn alloc(&mut self, node: IntervalNode) -> Link {
+        match self.free.pop() {
+            Some(link) => {
+                self.nodes[link] = node;
+                link
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn set_left(&mut self, node: Link, child: Link) {
+        self.nodes[node].left = child;
+        if child != SENTINEL {
+            self.nodes[child].parent = node;
+        }
+    }
+
+    fn set_right(&mut self, node: Link, child: Link) {
+        self.nodes[node].right = child;
+        if child != SENTINEL {
+            self.nodes[child].parent = node;
+        }
+    }
+
+    //	Recomputes the largest end of a node from its children.
+    fn update(&mut self, node: Link) {
+        let IntervalNode {
+            left,
+            right,
+            end,
+            lazy,
+            ..
+        } = self.nodes[node];
+        let mut max_end = end;
+        for child in [left, right] {
+            if child != SENTINEL {
+                max_end = max_end.max(self.nodes[child].max_end.wrapping_add_signed(lazy));
+            }
+        }
+        self.nodes[node].max_end = max_end;
+    }
+
+    //	Shifts every decoration of a subtree.
+    fn shift(&mut self, node: Link, delta: isize) {
+        let n = &mut self.nodes[node];
+        n.start = n.start.wrapping_add_signed(delta);
+        n.end = n.end.wrapping_add_signed(delta);
+        n.max_end = n.max_end.wrapping_add_signed(delta);
+        n.lazy += delta;
+    }
+
+    //	Applies the pending shift of a node to its children.
+    fn push(&mut self, node: Link) {
+        let IntervalNode {
+            lazy, left, right, ..
+        } = self.nodes[node];
+        if lazy == 0 {
+            return;
+        }
+        for child in [left, right] {
+            if child != SENTINEL {
+                self.shift(child, lazy);
+            }
+        }
+        self.nodes[node].lazy = 0;
+    }
+
+    //	Splits a subtree into the decorations starting before `offset`
+    //	and the rest.
+    fn split(&mut self, node: Link, offset: usize) -> (Link, Link) {
+        if node == SENTINEL {
+            return (SENTINEL, SENTINEL);
+        }
+        self.push(node);
+        if self.nodes[node].start < offset {
+            let (l, r) = self.split(self.nodes[node].right, offset);
+            self.set_right(node, l);
+            self.update(node);
+            (node, r)
+        } else {
+            let (l, r) = self.split(self.nodes[node].left, offset);
+            self.set_left(node, r);
+            self.update(node);
+            (l, node)
+        }
+    }
+
+    //	Joins two subtrees. Every decoration of `a` starts before or with
+    //	those of `b`.
+    fn merge(&mut self, a: Link, b: Link) -> Link {
+        if a == SENTINEL {
+            return b;
+        }
+        if b == SENTINEL {
+            return a;
+        }
+        if self.nodes[a].priority > self.nodes[b].priority {
+            self.push(a);
+            let right = self.merge(self.nodes[a].right, b);
+            self.set_right(a, right);
+            self.update(a);
+            a
+        } else {
+            self.push(b);
+            let left = self.merge(a, self.nodes[b].left);
+            self.set_left(b, left);
+            self.update(b);
+            b
+        }
+    }
+
+    //	Nodes of a subtree in order, with their shifts applied.
+    fn collect_nodes(&mut self, node: Link, ret: &mut Vec<Link>) {
+        if node == SENTINEL {
+            return;
+        }
+        self.push(node);
+        self.collect_nodes(self.nodes[node].left, ret);
+        ret.push(node);
+        self.collect_nodes(self.nodes[node].right, ret);
+    }
+
+    fn collect_range(
+        &self,
+        node: Link,
+        shift: isize,
+        range: &Range<usize>,
+        ret: &mut Vec<Decoration>,
+    ) {
+        if node == SENTINEL {
+            return;
+        }
+        let n = &self.nodes[node];
+        //	Nothing below ends inside the range.
+        if n.max_end.wrapping_add_signed(shift) < range.start {
+            return;
+        }
+        let start = n.start.wrapping_add_signed(shift);
+        let end = n.end.wrapping_add_signed(shift);
+        self.collect_range(n.left, shift + n.lazy, range, ret);
+        if start > range.end {
+            return;
+        }
+        if end >= range.start {
+            ret.push(self.decoration(node, shift));
+        }
+        self.collect_range(n.right, shift + n.lazy, range, ret);
+    }
+}
+
+//	New offset of a decoration edge after a change.
+fn move_edge(offset: usize, stickiness: Stickiness, is_start: bool, change: &Change) -> usize {
+    let (start, end) = (change.range.start, change.range.end);
+    let inserted = change.text.len();
+    if offset < start {
+        offset
+    } else if offset == start {
+        match stickiness {
+            Stickiness::Left => start,
+            Stickiness::Right => start + inserted,
+        }
+    } else if offset < end {
+        //	Inside the replaced text.
+        if is_start {
+            start + inserted
+        } else {
+            start
+        }
+    } else {
+        offset + inserted - change.range.len()
+    }
+}
@@ -0,0 +1,179 @@
+use std::borrow::Cow;
+
+use encoding_rs::{EncoderResult, UTF_16BE, UTF_16LE, WINDOWS_1252};
+
+use crate::{Error, Result};
+
+//	Encodings a text file can be read from and written back to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    //	ISO-8859-1, every byte is the code point of the same value.
+    Latin1,
+    Windows1252,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "ISO-8859-1",
+            Encoding::Windows1252 => "windows-1252",
+        }
+    }
+
+    //	Byte order mark of the encoding. Single byte encodings have none.
+    pub fn bom(&self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => b"\xEF\xBB\xBF",
+            Encoding::Utf16Le => b"\xFF\xFE",
+            Encoding::Utf16Be => b"\xFE\xFF",
+            Encoding::Latin1 | Encoding::Windows1252 => b"",
+        }
+    }
+}
+
+//	Text decoded from bytes, with what is needed to encode it back.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Decoded {
+    pub text: String,
+    pub encoding: Encoding,
+    pub bom: bool,
+    //	Whether malformed sequences were replaced with U+FFFD, so encoding
+    //	the text does not give the bytes back.
+    pub lossy: bool,
+}
+
+//	Detects the encoding of the bytes and whether they start with a
+//	byte order mark.
+pub fn detect(bytes: &[u8]) -> (Encoding, bool) {
+    for encoding in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be] {
+        if bytes.starts_with(encoding.bom()) {
+            return (encoding, true);
+        }
+    }
+    if let Some(encoding) = detect_utf16(bytes) {
+        return (encoding, false);
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return (Encoding::Utf8, false);
+    }
+    //	C1 control characters are rare in Latin-1 text, while Windows-1252
+    //	puts quotes and dashes there.
+    if bytes.iter().any(|b| (0x80..=0x9F).contains(b)) {
+        (Encoding::Windows1252, false)
+    } else {
+        (Encoding::Latin1, false)
+    }
+}
+
+//	Detects the encoding of a stream from its first bytes. Anything that
+//	is not UTF-16 is reported as UTF-8, since a single byte encoding can
+//	only be told apart from UTF-8 once invalid bytes turn up.
+pub fn detect_stream(head: &[u8]) -> (Encoding, bool) {
+    for encoding in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be] {
+        if head.starts_with(encoding.bom()) {
+            return (encoding, true);
+        }
+    }
+    let even = &head[..head.len() & !1];
+    (detect_utf16(even).unwrap_or(Encoding::Utf8), false)
+}
+
+//	Guesses UTF-16 without a byte order mark from the zero bytes mostly
+//	ASCII text has in every other byte.
+fn detect_utf16(bytes: &[u8]) -> Option<Encoding> {
+    let sample = &bytes[..bytes.len().min(4096)];
+    if sample.len() < 2 || !sample.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = sample.len() / 2;
+    let even = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|&&b| b == 0)
+        .count();
+    if odd * 2 > pairs && even == 0 {
+        Some(Encoding::Utf16Le)
+    } else if even * 2 > pairs && odd == 0 {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+//	Decodes bytes in the detected encoding. Latin-1 and Windows-1252
+//	accept any bytes, so decoding only replaces malformed UTF-16.
+pub fn decode(bytes: &[u8]) -> Decoded {
+    let (encoding, bom) = detect(bytes);
+    let bom_len = if bom { encoding.bom().len() } else { 0 };
+    let (text, lossy) = decode_as(&bytes[bom_len..], encoding);
+    Decoded {
+        text,
+        encoding,
+        bom,
+        lossy,
+    }
+}
+
+//	Decodes bytes without a byte order mark in an encoding. Malformed
+//	sequences are replaced with U+FFFD, which is returned as well.
+pub fn decode_as(bytes: &[u8], encoding: Encoding) -> (String, bool) {
+    let (txt, replaced) = match encoding {
+        Encoding::Utf8 => {
+            let txt = String::from_utf8_lossy(bytes);
+            let replaced = matches!(txt, Cow::Owned(_));
+            (txt, replaced)
+        }
+        Encoding::Utf16Le => UTF_16LE.decode_without_bom_handling(bytes),
+        Encoding::Utf16Be => UTF_16BE.decode_without_bom_handling(bytes),
+        Encoding::Latin1 => (bytes.iter().map(|&b| char::from(b)).collect(), false),
+        Encoding::Windows1252 => WINDOWS_1252.decode_without_bom_handling(bytes),
+    };
+    (txt.into_owned(), replaced)
+}
+
+//	Appends a chunk of text encoded in an encoding to `out`. `pos` is the
+//	position of the chunk in the text, used to report characters the
+//	encoding cannot represent.
+pub fn encode_into(txt: &str, pos: usize, encoding: Encoding, out: &mut Vec<u8>) -> Result<()> {
+    match encoding {
+        Encoding::Utf8 => out.extend_from_slice(txt.as_bytes()),
+        Encoding::Utf16Le => out.extend(txt.encode_utf16().flat_map(u16::to_le_bytes)),
+        Encoding::Utf16Be => out.extend(txt.encode_utf16().flat_map(u16::to_be_bytes)),
+        Encoding::Latin1 => {
+            for (i, c) in txt.char_indices() {
+                let b = u8::try_from(u32::from(c)).map_err(|_| Error::Unencodable {
+                    pos: pos + i,
+                    encoding: encoding.name(),
+                })?;
+                out.push(b);
+            }
+        }
+        Encoding::Windows1252 => {
+            //	Every character takes at most one byte, so the whole chunk
+            //	is encoded in one call.
+            let start = out.len();
+            out.resize(start + txt.len(), 0);
+            let (result, read, written) = WINDOWS_1252
+                .new_encoder()
+                .encode_from_utf8_without_replacement(txt, &mut out[start..], true);
+            if let EncoderResult::Unmappable(c) = result {
+                out.truncate(start);
+                return Err(Error::Unencodable {
+                    pos: pos + read - c.len_utf8(),
+                    encoding: encoding.name(),
+                });
+            }
+            out.truncate(start + written);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use crate::piecetree::LineStarts;
+
+//	Line ending inserted for new lines.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Eol {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl Eol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Eol::Lf => "\n",
+            Eol::CrLf => "\r\n",
+        }
+    }
+}
+
+//	Number of each kind of line ending in a text. A carriage return
+//	without a line feed is counted but does not end a line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct EolStats {
+    pub lf: usize,
+    pub crlf: usize,
+    pub cr: usize,
+}
+
+impl EolStats {
+    pub fn of(txt: &str) -> Self {
+        Self::of_chunks([txt])
+    }
+
+    //	Counts the line endings of a chunked text. A CRLF may be split
+    //	between two chunks.
+    pub fn of_chunks<'a, I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut stats = Self::default();
+        let mut prev_cr = false;
+        for chunk in chunks {
+            for b in chunk.bytes() {
+                match b {
+                    b'\n' if prev_cr => stats.crlf += 1,
+                    b'\n' => stats.lf += 1,
+                    _ if prev_cr => stats.cr += 1,
+                    _ => {}
+                }
+                prev_cr = b == b'\r';
+            }
+        }
+        if prev_cr {
+            stats.cr += 1;
+        }
+        stats
+    }
+
+    //	Sums the line endings counted while the line starts of each chunk
+    //	of a text were found. A CRLF split between two chunks was counted
+    //	as a CR and an LF.
+    pub fn of_line_starts<'a, I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, &'a LineStarts)>,
+    {
+        let mut stats = Self::default();
+        let mut prev_cr = false;
+        for (chunk, line_starts) in chunks {
+            let counts = Self::from(line_starts);
+            stats.lf += counts.lf;
+            stats.crlf += counts.crlf;
+            stats.cr += counts.cr;
+            if prev_cr && chunk.starts_with('\n') {
+                stats.cr -= 1;
+                stats.lf -= 1;
+                stats.crlf += 1;
+            }
+            if !chunk.is_empty() {
+                prev_cr = chunk.ends_with('\r');
+            }
+        }
+        stats
+    }
+
+    //	Line ending used by most lines, or None if there are no line
+    //	endings. Ties go to LF.
+    pub fn dominant(&self) -> Option<Eol> {
+        match (self.lf, self.crlf) {
+            (0, 0) => None,
+            (lf, crlf) if crlf > lf => Some(Eol::CrLf),
+            _ => Some(Eol::Lf),
+        }
+    }
+
+    //	Whether more than one kind of line ending is used.
+    pub fn is_mixed(&self) -> bool {
+        [self.lf, self.crlf, self.cr]
+            .iter()
+            .filter(|&&n| n > 0)
+            .count()
+            > 1
+    }
+}
+
+impl From<&LineStarts> for EolStats {
+    fn from(line_starts: &LineStarts) -> Self {
+        Self {
+            lf: line_starts.lf,
+            crlf: line_starts.crlf,
+            cr: line_starts.cr,
+        }
+    }
+}
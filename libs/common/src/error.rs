@@ -0,0 +1,82 @@
+use std::{fmt, io};
+
+//	Errors returned by the text buffers.
+#[derive(Debug)]
+pub enum Error {
+    //	Reading or writing a file failed.
+    Io(io::Error),
+    //	A position or range reaches past the end of the text.
+    OutOfBounds { pos: usize, len: usize },
+    //	A position falls inside a multibyte UTF-8 character.
+    InvalidBoundary(usize),
+    //	A range ends before it starts.
+    InvalidRange { start: usize, end: usize },
+    //	Two ranges of a batch edit overlap.
+    OverlappingRanges { start: usize, end: usize },
+    //	The character at a position cannot be written in an encoding.
+    Unencodable { pos: usize, encoding: &'static str },
+    //	The text was read with replacements for bytes that were not
+    //	valid, so writing it would lose them.
+    Lossy,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "i/o error: {}", err),
+            Error::OutOfBounds { pos, len } => {
+                write!(f, "position {} is out of bounds for length {}", pos, len)
+            }
+            Error::InvalidBoundary(pos) => {
+                write!(f, "position {} is not on a char boundary", pos)
+            }
+            Error::InvalidRange { start, end } => {
+                write!(f, "range {}..{} ends before it starts", start, end)
+            }
+            Error::OverlappingRanges { start, end } => {
+                write!(
+                    f,
+                    "range starting at {} overlaps a range ending at {}",
+                    start, end
+                )
+            }
+            Error::Unencodable { pos, encoding } => {
+                write!(
+                    f,
+                    "character at position {} cannot be encoded in {}",
+                    pos, encoding
+                )
+            }
+            Error::Lossy => {
+                write!(f, "text was read with invalid bytes replaced")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+//	Lets `io::Read` and `io::Write` implementations return buffer errors.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidInput, err),
+        }
+    }
+}
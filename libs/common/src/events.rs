@@ -0,0 +1,92 @@
+use std::ops::Range;
+
+//	One replaced range of the text.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Change {
+    //	Range of the replaced text before the change.
+    pub range: Range<usize>,
+    //	Text inserted at the start of the range.
+    pub text: String,
+    //	Line feeds in the replaced text.
+    pub removed_line_feeds: usize,
+    //	Line feeds in the inserted text.
+    pub inserted_line_feeds: usize,
+}
+
+impl Change {
+    pub fn new(range: Range<usize>, text: impl Into<String>, removed_line_feeds: usize) -> Self {
+        let text = text.into();
+        let inserted_line_feeds = text.bytes().filter(|&b| b == b'\n').count();
+        Self {
+            range,
+            text,
+            removed_line_feeds,
+            inserted_line_feeds,
+        }
+    }
+
+    pub fn removed_len(&self) -> usize {
+        self.range.len()
+    }
+
+    //	Change in the number of lines.
+    pub fn line_delta(&self) -> isize {
+        self.inserted_line_feeds as isize - self.removed_line_feeds as isize
+    }
+}
+
+//	What caused a change.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChangeReason {
+    Edit,
+    Undo,
+    Redo,
+}
+
+//	Sent to listeners after the text changed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChangeEvent {
+    //	Version of the text after the change. Grows with every change.
+    pub version: u64,
+    pub reason: ChangeReason,
+    //	Changes sorted from the end of the text to the start, so they can
+    //	be applied one after another to a copy of the old text.
+    pub changes: Vec<Change>,
+}
+
+pub type ListenerId = usize;
+
+pub type Listener = Box<dyn FnMut(&ChangeEvent) + Send>;
+
+//	Callbacks subscribed to the changes of a buffer.
+#[derive(Default)]
+pub struct Listeners {
+    next_id: ListenerId,
+    listeners: Vec<(ListenerId, Listener)>,
+}
+
+impl Listeners {
+    pub fn add(&mut self, listener: Listener) -> ListenerId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.listeners.push((id, listener));
+        id
+    }
+
+    //	Returns false if no listener had the id.
+    pub fn remove(&mut self, id: ListenerId) -> bool {
+        let len = self.listeners.len();
+        self.listeners.retain(|(i, _)| *i != id);
+        self.listeners.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    pub fn notify(&mut self, event: &ChangeEvent) {
+        for (_, listener) in self.listeners.iter_mut() {
+            listener(event);
+        }
+    }
+}
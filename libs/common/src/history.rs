@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+use crate::piecetable::Piece;
+
+//	Number of undo steps kept when no depth is configured.
+pub const DEFAULT_HISTORY_DEPTH: usize = 1000;
+
+//	A saved state of a piece table. The buffers are append-only so
+//	the pieces and the text length are enough to restore it.
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub pieces: Vec<Piece>,
+    pub text_len: usize,
+}
+
+impl HistoryEntry {
+    pub fn new(pieces: Vec<Piece>, text_len: usize) -> Self {
+        Self { pieces, text_len }
+    }
+}
+
+//	The last recorded edit. Used to group typing runs into one step.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EditKind {
+    //	Insert that ended at the position.
+    Insert(usize),
+    //	Delete that started at the position.
+    Delete(usize),
+}
+
+//	Undo and redo stacks of piece table states.
+pub struct History {
+    undo: VecDeque<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+    depth: usize,
+    last_edit: Option<EditKind>,
+}
+
+impl History {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            depth,
+            last_edit: None,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    //	Sets the maximum number of undo steps. The oldest undo steps and
+    //	the furthest redo steps are dropped if there are more than the
+    //	new depth.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+        //	The next redo step is on top of the stack.
+        let excess = self.redo.len().saturating_sub(depth);
+        self.redo.drain(..excess);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    //	Ends the current typing run so the next edit gets its own step.
+    pub fn break_group(&mut self) {
+        self.last_edit = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.last_edit = None;
+    }
+
+    //	Records an insert of `length` bytes at `pos`. `state` is only
+    //	called when the insert starts a new undo step.
+    pub fn record_insert<F>(&mut self, pos: usize, length: usize, state: F)
+    where
+        F: FnOnce() -> HistoryEntry,
+    {
+        let grouped = self.last_edit == Some(EditKind::Insert(pos));
+        self.record(grouped, EditKind::Insert(pos + length), state);
+    }
+
+    //	Records a delete of `length` bytes at `start`. Backspace and
+    //	forward delete runs are grouped into one step.
+    pub fn record_delete<F>(&mut self, start: usize, length: usize, state: F)
+    where
+        F: FnOnce() -> HistoryEntry,
+    {
+        let grouped = matches!(
+            self.last_edit,
+            Some(EditKind::Delete(last)) if last == start || last == start + length
+        );
+        self.record(grouped, EditKind::Delete(start), state);
+    }
+
+    //	Records an edit that never joins a typing run.
+    pub fn record_step<F>(&mut self, state: F)
+    where
+        F: FnOnce() -> HistoryEntry,
+    {
+        self.record(false, EditKind::Insert(0), state);
+        self.last_edit = None;
+    }
+
+    fn record<F>(&mut self, grouped: bool, kind: EditKind, state: F)
+    where
+        F: FnOnce() -> HistoryEntry,
+    {
+        self.redo.clear();
+        self.last_edit = Some(kind);
+        if grouped || self.depth == 0 {
+            return;
+        }
+        self.undo.push_back(state());
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+
+    //	Pops the last undo step and stores `current` so it can be redone.
+    pub fn undo(&mut self, current: HistoryEntry) -> Option<HistoryEntry> {
+        self.last_edit = None;
+        let entry = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(entry)
+    }
+
+    //	Pops the last redo step and stores `current` so it can be undone.
+    pub fn redo(&mut self, current: HistoryEntry) -> Option<HistoryEntry> {
+        self.last_edit = None;
+        let entry = self.redo.pop()?;
+        self.undo.push_back(current);
+        Some(entry)
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_DEPTH)
+    }
+}
@@ -1,4 +1,32 @@
 #![allow(dead_code)]
+pub mod buffer;
+pub mod builder;
+pub mod compaction;
+pub mod decorations;
+pub mod encoding;
+pub mod eol;
+pub mod error;
+pub mod events;
+pub mod history;
+pub mod markers;
 pub mod piecetable;
 pub mod piecetree;
+pub mod save;
+pub mod search;
+pub mod stream;
 mod tests;
+pub mod textbuffer;
+pub mod unicode;
+
+pub use builder::PieceTableBuilder;
+pub use compaction::CompactionPolicy;
+pub use decorations::{Decoration, DecorationId, RangeStickiness};
+pub use encoding::Encoding;
+pub use eol::{Eol, EolStats};
+pub use error::{Error, Result};
+pub use events::{Change, ChangeEvent, ChangeReason, ListenerId};
+pub use markers::{MarkerId, Stickiness};
+pub use regex::Regex;
+pub use save::SaveOptions;
+pub use search::{FindOptions, Match, Replaced};
+pub use textbuffer::{Edit, Position, TextBuffer, TextSnapshot};
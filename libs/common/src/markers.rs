@@ -0,0 +1,341 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::events::Change;
+
+//	Which side of text inserted at a marker the marker ends up on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stickiness {
+    //	The marker stays before the inserted text.
+    Left,
+    //	The marker moves after the inserted text.
+    Right,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct MarkerId(u64);
+
+type Link = usize;
+
+//	Node 0 is the empty tree.
+const SENTINEL: Link = 0;
+
+#[derive(Clone, Debug)]
+struct MarkerNode {
+    id: MarkerId,
+    stickiness: Stickiness,
+    //	Offset without the pending shifts of the ancestors.
+    offset: usize,
+    //	Shift not yet applied to the children.
+    lazy: isize,
+    priority: u64,
+    parent: Link,
+    left: Link,
+    right: Link,
+}
+
+//	Positions in a text that follow its edits. The markers are kept in a
+//	treap ordered by offset. Shifting every marker after an edit only
+//	updates O(log n) nodes, the rest is applied lazily.
+pub struct MarkerTree {
+    nodes: Vec<MarkerNode>,
+    free: Vec<Link>,
+    root: Link,
+    ids: HashMap<MarkerId, Link>,
+    next_id: u64,
+    //	State of the generator for node priorities.
+    seed: u64,
+    //	Markers removed because the text around them was deleted.
+    deleted: Vec<MarkerId>,
+}
+
+impl Default for MarkerTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarkerTree {
+    pub fn new() -> Self {
+        let sentinel = MarkerNode {
+            id: MarkerId(u64::MAX),
+            stickiness: Stickiness::Left,
+            offset: 0,
+            lazy: 0,
+            priority: 0,
+            parent: SENTINEL,
+            left: SENTINEL,
+            right: SENTINEL,
+        };
+        Self {
+            nodes: vec![sentinel],
+            free: Vec::new(),
+            root: SENTINEL,
+            ids: HashMap::new(),
+            next_id: 0,
+            seed: 0x2545_f491_4f6c_dd1d,
+            deleted: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn add(&mut self, offset: usize, stickiness: Stickiness) -> MarkerId {
+        let id = MarkerId(self.next_id);
+        self.next_id += 1;
+        let priority = self.next_priority();
+        let node = self.alloc(MarkerNode {
+            id,
+            stickiness,
+            offset,
+            lazy: 0,
+            priority,
+            parent: SENTINEL,
+            left: SENTINEL,
+            right: SENTINEL,
+        });
+        self.ids.insert(id, node);
+
+        let (l, r) = self.split(self.root, offset);
+        let l = self.merge(l, node);
+        self.root = self.merge(l, r);
+        self.nodes[self.root].parent = SENTINEL;
+        id
+    }
+
+    //	Returns false if there is no marker with the id.
+    pub fn remove(&mut self, id: MarkerId) -> bool {
+        let Some(node) = self.ids.remove(&id) else {
+            return false;
+        };
+        self.push(node);
+        let MarkerNode {
+            parent,
+            left,
+            right,
+            ..
+        } = self.nodes[node];
+        let merged = self.merge(left, right);
+        if parent == SENTINEL {
+            self.root = merged;
+            self.nodes[merged].parent = SENTINEL;
+        } else if self.nodes[parent].left == node {
+            self.set_left(parent, merged);
+        } else {
+            self.set_right(parent, merged);
+        }
+        self.free.push(node);
+        true
+    }
+
+    pub fn offset(&self, id: MarkerId) -> Option<usize> {
+        let node = *self.ids.get(&id)?;
+        let mut offset = self.nodes[node].offset;
+        let mut parent = self.nodes[node].parent;
+        while parent != SENTINEL {
+            offset = offset.wrapping_add_signed(self.nodes[parent].lazy);
+            parent = self.nodes[parent].parent;
+        }
+        Some(offset)
+    }
+
+    pub fn stickiness(&self, id: MarkerId) -> Option<Stickiness> {
+        self.ids.get(&id).map(|&n| self.nodes[n].stickiness)
+    }
+
+    //	Markers with an offset in the range, ordered by offset.
+    pub fn in_range(&self, range: Range<usize>) -> Vec<(MarkerId, usize)> {
+        let mut ret = Vec::new();
+        self.collect_range(self.root, 0, &range, &mut ret);
+        ret
+    }
+
+    //	Removes and returns the markers deleted by edits since the last call.
+    pub fn take_deleted(&mut self) -> Vec<MarkerId> {
+        std::mem::take(&mut self.deleted)
+    }
+
+    //	Moves the markers for a change. Markers strictly inside the
+    //	replaced range are removed and reported as deleted.
+    pub fn apply(&mut self, change: &Change) {
+        if self.root == SENTINEL {
+            return;
+        }
+        let (start, end) = (change.range.start, change.range.end);
+        let inserted = change.text.len();
+        let delta = inserted as isize - change.range.len() as isize;
+
+        //	Only the markers from `start` to `end` need to be looked at.
+        let (before, rest) = self.split(self.root, start);
+        let (touched, after) = self.split(rest, end + 1);
+        if after != SENTINEL {
+            self.shift(after, delta);
+        }
+
+        let mut nodes = Vec::new();
+        self.collect_nodes(touched, &mut nodes);
+        let mut at_start = SENTINEL;
+        let mut at_end = SENTINEL;
+        for node in nodes {
+            let MarkerNode {
+                id,
+                stickiness,
+                offset,
+                ..
+            } = self.nodes[node];
+            self.nodes[node].left = SENTINEL;
+            self.nodes[node].right = SENTINEL;
+            if offset == start && stickiness == Stickiness::Left {
+                at_start = self.merge(at_start, node);
+            } else if offset == start || offset == end {
+                self.nodes[node].offset = start + inserted;
+                at_end = self.merge(at_end, node);
+            } else {
+                self.ids.remove(&id);
+                self.free.push(node);
+                self.deleted.push(id);
+            }
+        }
+
+        let touched = self.merge(at_start, at_end);
+        let before = self.merge(before, touched);
+        self.root = self.merge(before, after);
+        self.nodes[self.root].parent = SENTINEL;
+    }
+
+    fn next_priority(&mut self) -> u64 {
+        //	xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    fn alloc(&mut self, node: MarkerNode) -> Link {
+        match self.free.pop() {
+            Some(link) => {
+                self.nodes[link] = node;
+                link
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn set_left(&mut self, node: Link, child: Link) {
+        self.nodes[node].left = child;
+        if child != SENTINEL {
+            self.nodes[child].parent = node;
+        }
+    }
+
+    fn set_right(&mut self, node: Link, child: Link) {
+        self.nodes[node].right = child;
+        if child != SENTINEL {
+            self.nodes[child].parent = node;
+        }
+    }
+
+    //	Shifts every marker of a subtree.
+    fn shift(&mut self, node: Link, delta: isize) {
+        let n = &mut self.nodes[node];
+        n.offset = n.offset.wrapping_add_signed(delta);
+        n.lazy += delta;
+    }
+
+    //	Applies the pending shift of a node to its children.
+    fn push(&mut self, node: Link) {
+        let MarkerNode {
+            lazy, left, right, ..
+        } = self.nodes[node];
+        if lazy == 0 {
+            return;
+        }
+        for child in [left, right] {
+            if child != SENTINEL {
+                self.shift(child, lazy);
+            }
+        }
+        self.nodes[node].lazy = 0;
+    }
+
+    //	Splits a subtree into the markers before `offset` and the rest.
+    fn split(&mut self, node: Link, offset: usize) -> (Link, Link) {
+        if node == SENTINEL {
+            return (SENTINEL, SENTINEL);
+        }
+        self.push(node);
+        if self.nodes[node].offset < offset {
+            let (l, r) = self.split(self.nodes[node].right, offset);
+            self.set_right(node, l);
+            (node, r)
+        } else {
+            let (l, r) = self.split(self.nodes[node].left, offset);
+            self.set_left(node, r);
+            (l, node)
+        }
+    }
+
+    //	Joins two subtrees. Every marker of `a` comes before those of `b`.
+    fn merge(&mut self, a: Link, b: Link) -> Link {
+        if a == SENTINEL {
+            return b;
+        }
+        if b == SENTINEL {
+            return a;
+        }
+        if self.nodes[a].priority > self.nodes[b].priority {
+            self.push(a);
+            let right = self.merge(self.nodes[a].right, b);
+            self.set_right(a, right);
+            a
+        } else {
+            self.push(b);
+            let left = self.merge(a, self.nodes[b].left);
+            self.set_left(b, left);
+            b
+        }
+    }
+
+    //	Nodes of a subtree in order, with their shifts applied.
+    fn collect_nodes(&mut self, node: Link, ret: &mut Vec<Link>) {
+        if node == SENTINEL {
+            return;
+        }
+        self.push(node);
+        self.collect_nodes(self.nodes[node].left, ret);
+        ret.push(node);
+        self.collect_nodes(self.nodes[node].right, ret);
+    }
+
+    fn collect_range(
+        &self,
+        node: Link,
+        lazy: isize,
+        range: &Range<usize>,
+        ret: &mut Vec<(MarkerId, usize)>,
+    ) {
+        if node == SENTINEL {
+            return;
+        }
+        let n = &self.nodes[node];
+        let offset = n.offset.wrapping_add_signed(lazy);
+        let lazy = lazy + n.lazy;
+        if offset >= range.start {
+            self.collect_range(n.left, lazy, range, ret);
+        }
+        if range.contains(&offset) {
+            ret.push((n.id, offset));
+        }
+        if offset < range.end {
+            self.collect_range(n.right, lazy, range, ret);
+        }
+    }
+}
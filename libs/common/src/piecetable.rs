@@ -1,66 +1,331 @@
-#![allow(unused_variables, unused_assignments)]
 use std::{
-    fs,
-    ops::{Index, IndexMut},
+    borrow::Cow,
+    fmt,
+    fs::{self, File},
+    io::{Read, Write},
+    ops::{Index, IndexMut, Range},
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use regex::Regex;
+
+pub use crate::textbuffer::Position;
+use crate::{
+    buffer::{Buffer, MappedText},
+    builder::PieceTableBuilder,
+    compaction::CompactionPolicy,
+    decorations::{Decoration, DecorationId, IntervalTree, RangeStickiness},
+    encoding::{self, Encoding},
+    eol::{Eol, EolStats},
+    events::{Change, ChangeEvent, ChangeReason, ListenerId, Listeners},
+    history::{History, HistoryEntry},
+    markers::{MarkerId, MarkerTree, Stickiness},
+    piecetree::{create_line_starts, scan_line_starts, LineStarts, AVERAGE_BUFFER_SIZE},
+    save::{self, SaveOptions},
+    search::{self, FindOptions, Match, Replaced},
+    stream::{Reader, Writer},
+    textbuffer::{Edit, TextSnapshot},
+    unicode::{self, OffsetUnit},
+    Error, Result,
 };
 
 //	Reference to a position in a buffer.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Piece {
-    is_orig: bool,
+    //	Index of the buffer the piece points into.
+    buffer_index: usize,
     start: usize,
     length: usize,
 }
 
 impl Piece {
-    fn new(is_orig: bool, start: usize, length: usize) -> Self {
+    fn new(buffer_index: usize, start: usize, length: usize) -> Self {
         Self {
-            is_orig,
+            buffer_index,
             start,
             length,
         }
     }
 }
 
-//	Two Buffers and an array of Pieces
+//	Bytes at the start of a mapped file its encoding and line ending
+//	are detected from.
+const DETECTION_SAMPLE: usize = 64 * 1024;
+
+//	Splits text into chunks of about `AVERAGE_BUFFER_SIZE` bytes so a
+//	large text is never held in one allocation.
+fn chunks_of(txt: &str) -> Vec<String> {
+    chunk_ranges(0..txt.len(), |i| txt.is_char_boundary(i))
+        .into_iter()
+        .map(|range| txt[range].to_string())
+        .collect()
+}
+
+//	Ranges of chunks of about `AVERAGE_BUFFER_SIZE` bytes that end on
+//	character boundaries.
+fn chunk_ranges<F>(range: Range<usize>, is_char_boundary: F) -> Vec<Range<usize>>
+where
+    F: Fn(usize) -> bool,
+{
+    let mut ranges = Vec::new();
+    let mut start = range.start;
+    while start < range.end {
+        let mut end = (start + AVERAGE_BUFFER_SIZE).min(range.end);
+        //	A character is at most 4 bytes long. Invalid text may have
+        //	no boundary close by.
+        let min_end = end.saturating_sub(3).max(start + 1);
+        while end > min_end && !is_char_boundary(end) {
+            end -= 1;
+        }
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
+
+//	Buffers of text and an array of Pieces pointing into them. The
+//	loaded text is split into chunk buffers, inserted text goes into add
+//	buffers after them.
 pub struct PieceTable {
-    buffers: Vec<String>,
+    //	Chunks of the loaded text followed by the add buffers. Inserted
+    //	text is appended to the last buffer.
+    buffers: Vec<Arc<Buffer>>,
+    //	Line starts of each buffer, found the first time they are needed.
+    //	The add buffer's line starts are extended on every insert.
+    line_starts: Vec<OnceLock<Vec<usize>>>,
+    //	Number of buffers holding the loaded text.
+    orig_buffers: usize,
+    //	Whether bytes were replaced when the text was decoded, or in
+    //	buffers that compaction replaced.
+    lossy: bool,
     pieces: Vec<Piece>,
     text_len: usize,
+    history: History,
+    //	Grows with every change of the text.
+    version: u64,
+    listeners: Listeners,
+    markers: MarkerTree,
+    decorations: IntervalTree,
+    //	Line ending inserted by `insert_newline`.
+    eol: Eol,
+    //	Encoding the text is written in and whether it starts with a
+    //	byte order mark.
+    encoding: Encoding,
+    bom: bool,
+    //	Pieces of the text as it was last loaded or saved.
+    save_point: Vec<Piece>,
+    compaction: CompactionPolicy,
+    //	Piece count at which the policy compacts next.
+    next_compaction: usize,
+    //	Text of several pieces copied into one string for regex
+    //	searches. Dropped on the next change of the text.
+    search_text: OnceLock<String>,
 }
 
 impl PieceTable {
     pub fn new(orig_txt: &str) -> Self {
-        Self {
-            buffers: vec![orig_txt.to_string(), String::new()],
-            pieces: vec![Piece::new(true, 0, orig_txt.len())],
-            text_len: orig_txt.len(),
+        let mut pt = Self {
+            buffers: Vec::new(),
+            line_starts: Vec::new(),
+            orig_buffers: 0,
+            lossy: false,
+            pieces: Vec::new(),
+            text_len: 0,
+            history: History::default(),
+            version: 0,
+            listeners: Listeners::default(),
+            markers: MarkerTree::default(),
+            decorations: IntervalTree::default(),
+            eol: Eol::default(),
+            encoding: Encoding::Utf8,
+            bom: false,
+            save_point: Vec::new(),
+            compaction: CompactionPolicy::default(),
+            next_compaction: 0,
+            search_text: OnceLock::new(),
+        };
+        let chunks = chunks_of(orig_txt);
+        let line_starts = chunks
+            .iter()
+            .map(|chunk| create_line_starts(Vec::new(), chunk))
+            .collect();
+        pt.eol = pt
+            .load_indexed(chunks, line_starts)
+            .dominant()
+            .unwrap_or_default();
+        pt
+    }
+
+    //	Reads a file in the encoding detected from its content.
+    pub fn from_file(file_path: &str) -> Result<Self> {
+        let bytes = fs::read(file_path)?;
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    //	Maps a file instead of reading it, so opening takes the same time
+    //	for any size. Each chunk's UTF-8 is validated and its lines are
+    //	found the first time it is read. Files in another encoding are
+    //	read like `from_file`.
+    //
+    //	The encoding is detected from the start of the file only. Bytes
+    //	after it that are not UTF-8 read as '?', which makes the table
+    //	lossy and keeps it from being written. `from_file` reads such a
+    //	file in a single byte encoding instead.
+    //
+    //	The file must not be changed by other programs while the table
+    //	or a snapshot of it is alive. Saving over it replaces the file,
+    //	which keeps the map intact.
+    pub fn from_file_mapped(file_path: &str) -> Result<Self> {
+        let file = File::open(file_path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(Self::new(""));
+        }
+        let text = Arc::new(MappedText::open(&file)?);
+
+        //	Only the start of the file is used to detect the encoding and
+        //	line ending.
+        let mut sample_len = text.len().min(DETECTION_SAMPLE);
+        while !text.is_char_boundary(sample_len) {
+            sample_len -= 1;
         }
+        let sample = &text.as_bytes()[..sample_len];
+        let (encoding, bom) = encoding::detect(sample);
+        if encoding != Encoding::Utf8 {
+            return Self::from_file(file_path);
+        }
+        let eol = std::str::from_utf8(sample)
+            .map(|txt| EolStats::of(txt).dominant().unwrap_or_default())
+            .unwrap_or_default();
+
+        let start = if bom { encoding.bom().len() } else { 0 };
+        let chunks = chunk_ranges(start..text.len(), |i| text.is_char_boundary(i))
+            .into_iter()
+            .map(|range| Buffer::mapped(Arc::clone(&text), range))
+            .collect();
+        let mut pt = Self::new("");
+        pt.load_chunks(chunks);
+        pt.eol = eol;
+        pt.bom = bom;
+        Ok(pt)
     }
 
-    pub fn from_file(file_path: &str) -> Self {
-        let orig_txt = fs::read_to_string(file_path).expect("Error reading file.");
+    //	Reads text from any reader in chunks, like piped input or a
+    //	decompressor. See `PieceTableBuilder`.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut builder = PieceTableBuilder::new();
+        builder.read_from(reader)?;
+        Ok(builder.finish())
+    }
 
-        Self {
-            buffers: vec![orig_txt.to_string(), String::new()],
-            pieces: vec![Piece::new(true, 0, orig_txt.len())],
-            text_len: orig_txt.len(),
+    //	Table of text that was loaded in chunks, with the line starts of
+    //	each chunk.
+    pub(crate) fn from_loaded(
+        chunks: Vec<String>,
+        line_starts: Vec<LineStarts>,
+        encoding: Encoding,
+        bom: bool,
+        lossy: bool,
+    ) -> Self {
+        let mut pt = Self::new("");
+        pt.eol = pt
+            .load_indexed(chunks, line_starts)
+            .dominant()
+            .unwrap_or_default();
+        pt.encoding = encoding;
+        pt.bom = bom;
+        pt.lossy = lossy;
+        pt
+    }
+
+    //	Decodes bytes in the encoding detected from them. The encoding
+    //	and byte order mark are kept for writing the text back.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let decoded = encoding::decode(bytes);
+        let mut pt = Self::new(&decoded.text);
+        pt.encoding = decoded.encoding;
+        pt.bom = decoded.bom;
+        pt.lossy = decoded.lossy;
+        pt
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    //	Sets the encoding used by `write_to`.
+    pub fn set_encoding(&mut self, encoding: Encoding, bom: bool) {
+        self.encoding = encoding;
+        self.bom = bom && !encoding.bom().is_empty();
+    }
+
+    //	Writes the text in its encoding, starting with the byte order
+    //	mark if it had one. Fails on the first character the encoding
+    //	cannot represent, and before writing anything if the table is
+    //	lossy.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        if self.is_lossy() {
+            return Err(Error::Lossy);
+        }
+        if self.bom {
+            writer.write_all(self.encoding.bom())?;
+        }
+        let mut pos = 0;
+        let mut out = Vec::new();
+        for chunk in self.chunks(None, None) {
+            out.clear();
+            encoding::encode_into(chunk, pos, self.encoding, &mut out)?;
+            writer.write_all(&out)?;
+            pos += chunk.len();
         }
+        writer.flush()?;
+        Ok(())
+    }
+
+    //	Writes the text to a file atomically in its encoding and makes
+    //	the current text the save point.
+    pub fn save_to<P: AsRef<Path>>(&mut self, path: P, options: &SaveOptions) -> Result<()> {
+        save::write_atomic(path.as_ref(), options, |writer| self.write_to(writer))?;
+        self.save_point = self.pieces.clone();
+        Ok(())
+    }
+
+    //	Whether bytes of the loaded text were not valid in its encoding
+    //	and were replaced, with U+FFFD when decoded or with '?' in a
+    //	mapped file. Writing such a table would not give the bytes back,
+    //	so it fails. Validates every chunk of a mapped file that was not
+    //	read yet.
+    pub fn is_lossy(&self) -> bool {
+        self.lossy
+            || self.buffers[..self.orig_buffers]
+                .iter()
+                .any(|buffer| buffer.is_lossy())
+    }
+
+    //	Whether the text changed since it was loaded or last saved.
+    //	Undoing back to the save point makes the text clean again.
+    pub fn is_dirty(&self) -> bool {
+        let shared = shared_len(self.save_point.iter(), self.pieces.iter(), false);
+        let saved_len: usize = self.save_point.iter().map(|p| p.length).sum();
+        shared != saved_len || shared != self.text_len
     }
 
     pub fn find_by_pos(&self, char_pos: usize) -> Option<&Piece> {
-        //	Text buffer positions for the current slice.
-        let mut txt_start = 0;
+        //	Text buffer end of the current slice.
         let mut txt_end = 0;
 
         //	Iterate over pieces until you print the substring.
         for i in 0..self.len() {
             let ip = &self[i];
-            //	Set start to previous end and increment the end
-            //	by the piece length.
-            txt_start = txt_end;
+            //	The slice starts at the previous end and ends after
+            //	the piece length.
+            let txt_start = txt_end;
             txt_end += ip.length;
-            if char_pos >= txt_start || char_pos <= txt_end {
+            if char_pos >= txt_start && char_pos < txt_end {
                 return Some(ip);
             }
         }
@@ -72,24 +337,78 @@ impl PieceTable {
         self.pieces.len()
     }
 
+    //	Number of buffers, the chunks of the loaded text followed by the
+    //	add buffers.
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    //	Length of the text in bytes.
+    pub fn text_len(&self) -> usize {
+        self.text_len
+    }
+
     fn get_buffer_len(&self, buffer_index: usize) -> usize {
         self.buffers[buffer_index].len()
     }
 
-    fn get_buffer_slice<'a>(&self, buffer: &str, start: usize, end: usize) -> String {
-        buffer[start..end].to_string()
+    //	Removes `length` bytes from a piece starting at `start_offset`.
+    //
+    //	Panics if the piece index is out of range or the slice splits a
+    //	character. Use `try_trim_piece` to get an error instead.
+    pub fn trim_piece(&mut self, piece_index: usize, start_offset: usize, length: usize) -> usize {
+        self.try_trim_piece(piece_index, start_offset, length)
+            .expect("Error trimming piece.")
     }
 
-    pub fn trim_piece(&mut self, piece_index: usize, start_offset: usize, length: usize) -> usize {
-        let piece: &mut Piece = &mut self[piece_index];
+    pub fn try_trim_piece(
+        &mut self,
+        piece_index: usize,
+        start_offset: usize,
+        length: usize,
+    ) -> Result<usize> {
+        let piece = *self.pieces.get(piece_index).ok_or(Error::OutOfBounds {
+            pos: piece_index,
+            len: self.len(),
+        })?;
+        if start_offset > piece.length {
+            return Err(Error::OutOfBounds {
+                pos: start_offset,
+                len: piece.length,
+            });
+        }
+        let len = length.min(piece.length - start_offset);
+        let buffer = &self.buffers[piece.buffer_index];
+        for offset in [start_offset, start_offset + len] {
+            if !buffer.is_char_boundary(piece.start + offset) {
+                return Err(Error::InvalidBoundary(offset));
+            }
+        }
 
-        let len: usize; //  Length of the slice to remove from the piece.
+        let (pieces, text_len) = (&self.pieces, self.text_len);
+        self.history
+            .record_step(|| HistoryEntry::new(pieces.clone(), text_len));
+        let offset: usize = self.pieces[..piece_index].iter().map(|p| p.length).sum();
+        let removed = Piece::new(piece.buffer_index, piece.start + start_offset, len);
+        let removed_line_feeds = self.piece_line_feeds(&removed);
+        let trimmed = self.trim_piece_at(piece_index, start_offset, len);
+        self.text_len -= trimmed;
 
-        if length > piece.length {
-            len = piece.length - start_offset;
-        } else {
-            len = length;
-        }
+        let start = offset + start_offset;
+        let change = Change::new(start..start + trimmed, String::new(), removed_line_feeds);
+        self.emit(ChangeReason::Edit, vec![change]);
+        Ok(trimmed)
+    }
+
+    fn trim_piece_at(&mut self, piece_index: usize, start_offset: usize, length: usize) -> usize {
+        let piece: &mut Piece = &mut self[piece_index];
+
+        //  Length of the slice to remove from the piece.
+        let len = length.min(piece.length - start_offset);
 
         //  Example input Piece = "Hello, World!" : Indexes [0..13]
         //  start_offset = 0
@@ -127,8 +446,8 @@ impl PieceTable {
             let l_len = start_offset;
             let r_start = r_end;
             let r_len = end_offset;
-            let lp = Piece::new(piece.is_orig, l_start, l_len);
-            let rp = Piece::new(piece.is_orig, r_start, r_len);
+            let lp = Piece::new(piece.buffer_index, l_start, l_len);
+            let rp = Piece::new(piece.buffer_index, r_start, r_len);
             self.pieces.remove(piece_index);
             self.pieces.insert(piece_index, rp);
             self.pieces.insert(piece_index, lp);
@@ -137,280 +456,1478 @@ impl PieceTable {
         r_end - r_start
     }
 
+    //	Inserts text at a position. Positions past the end of the text
+    //	append to it.
+    //
+    //	Panics if the position splits a character. Use `try_insert` to
+    //	get an error instead.
     pub fn insert(&mut self, txt: &str, pos: usize) {
-        let start = self.get_buffer_len(1);
-        self.buffers[1].push_str(txt);
-        let p = Piece::new(false, start, txt.len());
+        let pos = pos.min(self.text_len);
+        self.try_insert(txt, pos).expect("Error inserting text.");
+    }
 
-        let mut piece_index = 0;
-        //	Whether the pos falls on the start of another piece.
-        let mut on_boundary = false;
-        let mut on_start = false;
+    pub fn try_insert(&mut self, txt: &str, pos: usize) -> Result<()> {
+        self.check_pos(pos)?;
+        if txt.is_empty() {
+            return Ok(());
+        }
+        let (pieces, text_len) = (&self.pieces, self.text_len);
+        self.history.record_insert(pos, txt.len(), || {
+            HistoryEntry::new(pieces.clone(), text_len)
+        });
 
-        let mut txt_start = 0;
-        let mut txt_end = 0;
-        let mut success = false;
-
-        //	Find the position of the piece based on its place
-        //	in the Pieces vec. If the position is out of the
-        //	bounds of the vec then it will be appended to the
-        //	piece vec.
-        //	Otherwise the piece that it falls on will be split into
-        //	two seperate parts. Then the new piece will be inserted
-        //	inbetween the previous ones.
-        for i in 0..self.len() {
-            let ip = &self[i];
-            txt_start = txt_end;
-            txt_end += ip.length;
-            if pos >= txt_start && pos <= txt_end {
-                piece_index = i;
-                if pos == txt_start {
-                    on_boundary = true;
-                    //	vec.insert(item, index) inserts at that
-                    //	index and shifts to the right.
-                    //	If the txt pos is on the start of this piece
-                    //	we want to insert to the left of the current
-                    //	piece.
-                    on_start = true;
-                } else if pos == txt_end {
-                    on_boundary = true;
-                }
-                break;
+        //	Split the piece the position falls on and insert the
+        //	new piece inbetween the two parts.
+        let piece_index = self.split_at(pos);
+        let p = self.append_to_add_buffer(txt);
+        match piece_index.checked_sub(1).map(|i| &mut self.pieces[i]) {
+            //	Typing right after the last insert continues its piece,
+            //	since the new text follows it in the add buffer.
+            Some(prev)
+                if prev.buffer_index == p.buffer_index && prev.start + prev.length == p.start =>
+            {
+                prev.length += p.length;
             }
+            _ => self.pieces.insert(piece_index, p),
+        }
+        self.text_len += p.length;
+        self.emit(ChangeReason::Edit, vec![Change::new(pos..pos, txt, 0)]);
+        Ok(())
+    }
+
+    //	Appends text to the last add buffer and returns a piece for it.
+    //	A new add buffer is started once the last one would outgrow a
+    //	chunk, so the add buffers are never reallocated past that size.
+    fn append_to_add_buffer(&mut self, txt: &str) -> Piece {
+        let mut index = self.buffers.len() - 1;
+        let len = self.get_buffer_len(index);
+        if len > 0 && len + txt.len() > AVERAGE_BUFFER_SIZE {
+            self.buffers.push(Arc::default());
+            self.line_starts.push(OnceLock::new());
+            index += 1;
+        }
+        let start = self.get_buffer_len(index);
+        //	Only copies the add buffer if a snapshot still shares it.
+        Arc::make_mut(&mut self.buffers[index]).push_str(txt);
+        //	Only the new text is scanned for line feeds.
+        if let Some(line_starts) = self.line_starts[index].get_mut() {
+            let new_starts = scan_line_starts(txt.as_bytes());
+            line_starts.extend(new_starts[1..].iter().map(|ls| ls + start));
+        }
+        Piece::new(index, start, txt.len())
+    }
+
+    //	Makes chunks of loaded text the first buffers, one piece each,
+    //	followed by an empty add buffer. The line starts of a chunk are
+    //	found the first time they are needed.
+    fn load_chunks(&mut self, mut chunks: Vec<Buffer>) {
+        if chunks.is_empty() {
+            chunks.push(Buffer::default());
+        }
+        self.pieces = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| Piece::new(i, 0, chunk.len()))
+            .collect();
+        self.text_len = chunks.iter().map(|chunk| chunk.len()).sum();
+        self.save_point = self.pieces.clone();
+        self.orig_buffers = chunks.len();
+        self.buffers = chunks.into_iter().map(Arc::new).collect();
+        self.buffers.push(Arc::default());
+        self.line_starts = vec![OnceLock::new(); self.buffers.len()];
+    }
+
+    //	Loads chunks whose line starts were already found. Their line
+    //	endings were counted along with them, so the text is not scanned
+    //	again to detect the line ending.
+    fn load_indexed(&mut self, chunks: Vec<String>, line_starts: Vec<LineStarts>) -> EolStats {
+        let stats = EolStats::of_line_starts(chunks.iter().map(String::as_str).zip(&line_starts));
+        self.load_chunks(chunks.into_iter().map(Buffer::from).collect());
+        for (i, line_starts) in line_starts.iter().enumerate() {
+            self.line_starts[i] = OnceLock::from(line_starts.line_starts.to_vec());
+        }
+        stats
+    }
+
+    //	First match of a literal needle that starts at or after `from`.
+    pub fn find(&self, needle: &str, from: usize, options: &FindOptions) -> Option<Match> {
+        let mut from = from.min(self.text_len);
+        while !self.is_char_boundary(from) {
+            from += 1;
+        }
+        let mut found = None;
+        self.find_from(from, needle, options, |m| {
+            found = Some(m);
+            false
+        });
+        found
+    }
+
+    //	Every match of a literal needle. Matches do not overlap.
+    pub fn find_all(&self, needle: &str, options: &FindOptions) -> Vec<Match> {
+        let mut found = Vec::new();
+        self.find_from(0, needle, options, |m| {
+            found.push(m);
+            true
+        });
+        found
+    }
+
+    fn find_from<F>(&self, from: usize, needle: &str, options: &FindOptions, on_match: F)
+    where
+        F: FnMut(Match) -> bool,
+    {
+        let Some(pos) = self.position_at(from) else {
+            return;
+        };
+        //	Whole word searches need the character before the start.
+        let mut before = from.saturating_sub(4);
+        while !self.is_char_boundary(before) {
+            before += 1;
+        }
+        let prev = self
+            .chunks(Some(before), Some(from))
+            .flat_map(str::chars)
+            .last();
+        search::find_in_chunks(
+            self.chunks(Some(from), None),
+            from,
+            pos,
+            prev,
+            needle,
+            options,
+            on_match,
+        );
+    }
+
+    //	First match of a regex that starts at or after `from`. Patterns
+    //	can match across lines and pieces.
+    //
+    //	Regexes search one slice, so the first search after a change
+    //	copies the text into one string when it has more than one piece.
+    //	That copy is as large as the text, even for a mapped file, and
+    //	is kept for the following searches until the text changes.
+    pub fn find_regex(&self, regex: &Regex, from: usize) -> Option<Match> {
+        let text = self.contiguous_text();
+        let mut from = from.min(text.len());
+        while !text.is_char_boundary(from) {
+            from += 1;
         }
+        let mut found = None;
+        search::find_regex(text, from, regex, |m| {
+            found = Some(m);
+            false
+        });
+        found
+    }
+
+    //	Every match of a regex, in the order `Regex::find_iter` gives.
+    pub fn find_all_regex(&self, regex: &Regex) -> Vec<Match> {
+        let mut found = Vec::new();
+        search::find_regex(self.contiguous_text(), 0, regex, |m| {
+            found.push(m);
+            true
+        });
+        found
+    }
+
+    //	Replaces every match of a regex as one undo step. `$1` and
+    //	`${name}` in the template expand to capture groups, like in
+    //	`Regex::replace_all`.
+    pub fn replace_all(&mut self, regex: &Regex, template: &str) -> Replaced {
+        let edits: Vec<Edit> = regex
+            .captures_iter(self.contiguous_text())
+            .map(|caps| {
+                let mut text = String::new();
+                caps.expand(template, &mut text);
+                Edit::new(caps.get_match().range(), text)
+            })
+            .collect();
+        if edits.is_empty() {
+            return Replaced::default();
+        }
+        self.apply_edits(&edits).expect("Error replacing matches.");
+
+        let mut delta: isize = 0;
+        let ranges = edits
+            .iter()
+            .map(|edit| {
+                let start = edit.range.start.wrapping_add_signed(delta);
+                delta += edit.text.len() as isize - edit.range.len() as isize;
+                start..start + edit.text.len()
+            })
+            .collect();
+        Replaced {
+            count: edits.len(),
+            ranges,
+        }
+    }
+
+    //	The text as one string for searches that need it in one slice.
+    //	Text spread over several pieces is copied once and reused until
+    //	the text changes.
+    fn contiguous_text(&self) -> &str {
+        match self.pieces.as_slice() {
+            [] => "",
+            [p] => &self.buffers[p.buffer_index][p.start..p.start + p.length],
+            _ => self.search_text.get_or_init(|| self.get_text(None, None)),
+        }
+    }
+
+    //	Inserts the configured line ending at a position. Positions past
+    //	the end of the text append to it.
+    //
+    //	Panics if the position splits a character. Use
+    //	`try_insert_newline` to get an error instead.
+    pub fn insert_newline(&mut self, pos: usize) {
+        let pos = pos.min(self.text_len);
+        self.try_insert_newline(pos)
+            .expect("Error inserting line ending.");
+    }
+
+    pub fn try_insert_newline(&mut self, pos: usize) -> Result<()> {
+        self.try_insert(self.eol.as_str(), pos)
+    }
+
+    //	Line ending used for new lines. Detected from the text on load.
+    pub fn eol(&self) -> Eol {
+        self.eol
+    }
+
+    //	Counts the line endings of the current text.
+    pub fn eol_stats(&self) -> EolStats {
+        EolStats::of_chunks(self.chunks(None, None))
+    }
 
-        if pos > txt_end {
-            on_boundary = true;
+    pub fn has_mixed_eol(&self) -> bool {
+        self.eol_stats().is_mixed()
+    }
+
+    //	Converts every line ending of the text and uses `eol` for new
+    //	lines. The conversion is one undo step. A carriage return without
+    //	a line feed is not a line ending and is kept.
+    pub fn set_eol(&mut self, eol: Eol) {
+        self.eol = eol;
+        let mut edits = Vec::new();
+        let mut pos = 0;
+        let mut prev_cr = false;
+        for chunk in self.chunks(None, None) {
+            for b in chunk.bytes() {
+                if b == b'\n' {
+                    match (eol, prev_cr) {
+                        (Eol::Lf, true) => edits.push(Edit::delete(pos - 1..pos)),
+                        (Eol::CrLf, false) => edits.push(Edit::insert(pos, "\r")),
+                        _ => {}
+                    }
+                }
+                prev_cr = b == b'\r';
+                pos += 1;
+            }
+        }
+        if !edits.is_empty() {
+            self.apply_edits(&edits)
+                .expect("Error converting line endings.");
         }
+    }
+
+    //	Replaces several ranges at once. The ranges are positions in the
+    //	text before any of the edits and may not overlap. Either every
+    //	edit is applied as one undo step or none is.
+    //
+    //	Returns the edits that revert the batch when applied with
+    //	`apply_edits`, in the order the edits were applied: sorted by
+    //	range, with edits at the same position in input order.
+    pub fn apply_edits(&mut self, edits: &[Edit]) -> Result<Vec<Edit>> {
+        let mut order: Vec<usize> = (0..edits.len()).collect();
+        order.sort_by_key(|&i| (edits[i].range.start, edits[i].range.end));
 
-        //	If the piece should be appended.
-        if on_boundary {
-            if on_start {
-                self.pieces.insert(piece_index, p);
-                success = true;
-            } else {
-                self.pieces.insert(piece_index + 1, p);
-                success = true;
+        //	Validate every range before changing anything.
+        let mut prev_end = 0;
+        for &i in order.iter() {
+            let range = &edits[i].range;
+            if range.start > range.end {
+                return Err(Error::InvalidRange {
+                    start: range.start,
+                    end: range.end,
+                });
             }
+            if range.start < prev_end {
+                return Err(Error::OverlappingRanges {
+                    start: range.start,
+                    end: prev_end,
+                });
+            }
+            self.check_pos(range.start)?;
+            self.check_pos(range.end)?;
+            prev_end = range.end;
         }
-        //	Split the piece the position falls on and insert the
-        //	new pieces.
-        //	Example:
-        //	Buffer contains the pos 64, which falls on a piece
-        //	that starts at txt_start=32 and ends at txt_end=128.
-        //	The buffer would be split [32..64] [64..128].
-        //	Modify the original piece to end at 64.
-        //	Append the new piece and the other part of the original.
-        else {
-            let orig = &mut self[piece_index];
-            //	Find the remainder in the original piece.
-            // 	Remainder = 64 - 32 = 32
-            let remainder = pos - txt_start;
-            // 	Second piece length = 96 - 32 = 64
-            let new_len = orig.length - remainder;
-            //	Set the first piece's length to the remainder.
-            orig.length = remainder;
-            let new_start = orig.start + remainder;
-            let new_p = Piece::new(orig.is_orig, new_start, new_len);
-            let new_index = piece_index + 1;
-            self.pieces.insert(new_index, new_p);
-            self.pieces.insert(new_index, p);
-            success = true;
+        if edits
+            .iter()
+            .all(|e| e.range.is_empty() && e.text.is_empty())
+        {
+            return Ok(edits.to_vec());
         }
 
-        if success {
-            self.text_len += p.length;
+        let (pieces, text_len) = (&self.pieces, self.text_len);
+        self.history
+            .record_step(|| HistoryEntry::new(pieces.clone(), text_len));
+
+        //	Walk the pieces and the sorted edits together, copying the
+        //	pieces between edits and skipping the replaced ranges.
+        let old_pieces = std::mem::take(&mut self.pieces);
+        let mut cursor = PieceCursor::new(&old_pieces);
+        let mut new_pieces = Vec::with_capacity(old_pieces.len() + 2 * edits.len());
+        let mut inverse = Vec::with_capacity(edits.len());
+        let mut changes = Vec::with_capacity(edits.len());
+        let mut delta: isize = 0;
+
+        for &i in order.iter() {
+            let edit = &edits[i];
+            cursor.copy_until(edit.range.start, &mut new_pieces);
+            let mut removed = String::new();
+            cursor.skip_until(edit.range.end, |p| {
+                let buffer = &self.buffers[p.buffer_index];
+                removed.push_str(&buffer[p.start..p.start + p.length]);
+            });
+            if !edit.text.is_empty() {
+                let p = self.append_to_add_buffer(&edit.text);
+                new_pieces.push(p);
+            }
+
+            let removed_line_feeds = removed.bytes().filter(|&b| b == b'\n').count();
+            changes.push(Change::new(
+                edit.range.clone(),
+                edit.text.clone(),
+                removed_line_feeds,
+            ));
+
+            let start = edit.range.start.wrapping_add_signed(delta);
+            inverse.push(Edit::new(start..start + edit.text.len(), removed));
+            delta += edit.text.len() as isize - edit.range.len() as isize;
         }
+        cursor.copy_until(self.text_len, &mut new_pieces);
+
+        self.pieces = new_pieces;
+        self.text_len = self.text_len.wrapping_add_signed(delta);
+        changes.reverse();
+        self.emit(ChangeReason::Edit, changes);
+        Ok(inverse)
     }
 
+    //	Deletes `length` bytes starting at `start`. The length is clamped
+    //	to the end of the text.
+    //
+    //	Panics if the range splits a character. Use `try_delete` to get
+    //	an error instead.
     pub fn delete(&mut self, start: usize, length: usize) {
-        let len: usize; //  Length of the slice to remove.
+        if start >= self.text_len {
+            return;
+        }
+        let len = length.min(self.text_len - start);
+        self.try_delete(start, len).expect("Error deleting text.");
+    }
 
+    //	Returns the number of bytes removed.
+    pub fn try_delete(&mut self, start: usize, length: usize) -> Result<usize> {
+        let end = start.checked_add(length).ok_or(Error::OutOfBounds {
+            pos: usize::MAX,
+            len: self.text_len,
+        })?;
+        self.check_pos(start)?;
+        self.check_pos(end)?;
         if length == 0 {
-            return;
+            return Ok(0);
         }
 
-        if (start + length) > self.text_len {
-            len = self.text_len - start;
-        } else {
-            len = length;
+        let (pieces, text_len) = (&self.pieces, self.text_len);
+        self.history.record_delete(start, length, || {
+            HistoryEntry::new(pieces.clone(), text_len)
+        });
+
+        //	Split the pieces at both ends of the slice and remove the
+        //	pieces inbetween.
+        let piece_start = self.split_at(start);
+        let piece_end = self.split_at(end);
+        let removed_line_feeds = self.pieces[piece_start..piece_end]
+            .iter()
+            .map(|p| self.piece_line_feeds(p))
+            .sum();
+        self.pieces.drain(piece_start..piece_end);
+        self.text_len -= length;
+
+        let change = Change::new(start..end, String::new(), removed_line_feeds);
+        self.emit(ChangeReason::Edit, vec![change]);
+        Ok(length)
+    }
+
+    //	Splits the piece containing `pos` so that a piece starts at `pos`.
+    //	Returns the index of that piece, or the piece count if `pos` is
+    //	the end of the text.
+    //	Example:
+    //	Buffer contains the pos 64, which falls on a piece
+    //	that starts at txt_start=32 and ends at txt_end=128.
+    //	The piece would be split [32..64] [64..128].
+    fn split_at(&mut self, pos: usize) -> usize {
+        let mut txt_end = 0;
+        for i in 0..self.len() {
+            let txt_start = txt_end;
+            txt_end += self[i].length;
+            if pos == txt_start {
+                return i;
+            }
+            if pos < txt_end {
+                let orig = &mut self[i];
+                //	Find the remainder in the original piece.
+                let remainder = pos - txt_start;
+                let new_p = Piece::new(
+                    orig.buffer_index,
+                    orig.start + remainder,
+                    orig.length - remainder,
+                );
+                //	Set the first piece's length to the remainder.
+                orig.length = remainder;
+                self.pieces.insert(i + 1, new_p);
+                return i + 1;
+            }
+        }
+        self.len()
+    }
+
+    //	Whether a position is in the text and not inside a character.
+    pub fn is_char_boundary(&self, pos: usize) -> bool {
+        match self.byte_at(pos) {
+            //	UTF-8 continuation bytes are 0b10xxxxxx.
+            Some(b) => (b as i8) >= -0x40,
+            None => pos == self.text_len,
+        }
+    }
+
+    fn check_pos(&self, pos: usize) -> Result<()> {
+        if pos > self.text_len {
+            return Err(Error::OutOfBounds {
+                pos,
+                len: self.text_len,
+            });
+        }
+        if !self.is_char_boundary(pos) {
+            return Err(Error::InvalidBoundary(pos));
+        }
+        Ok(())
+    }
+
+    //	Start and end are absolute positions in the buffer.
+    //
+    //	Panics if start or end split a character. Use `try_get_text` to
+    //	get an error instead.
+    pub fn get_text(&self, s_start: Option<usize>, s_end: Option<usize>) -> String {
+        self.chunks(s_start, s_end).collect()
+    }
+
+    //	Reads the text as bytes without copying it first.
+    pub fn reader(&self) -> Reader<'_> {
+        Reader::new(self.chunks(None, None))
+    }
+
+    //	Appends written text to the end.
+    pub fn writer(&mut self) -> Writer<'_> {
+        let pos = self.text_len;
+        Writer::new(self, pos)
+    }
+
+    //	Inserts written text at a position and moves past it.
+    pub fn writer_at(&mut self, pos: usize) -> Writer<'_> {
+        Writer::new(self, pos)
+    }
+
+    //	Iterates over the text one piece at a time without copying it.
+    //	Start and end are absolute positions that clip the first and
+    //	last piece. A range that ends before it starts is empty.
+    pub fn chunks(&self, s_start: Option<usize>, s_end: Option<usize>) -> Chunks<'_> {
+        //	Initialize optional args.
+        let end = s_end.unwrap_or(self.text_len).min(self.text_len);
+        let start = s_start.unwrap_or(0).min(end);
+        Chunks {
+            table: self,
+            index: 0,
+            txt_end: 0,
+            start,
+            end,
+        }
+    }
+
+    pub fn try_get_text(&self, s_start: Option<usize>, s_end: Option<usize>) -> Result<String> {
+        let start = s_start.unwrap_or(0);
+        let end = s_end.unwrap_or(self.text_len);
+        if start > end {
+            return Err(Error::InvalidRange { start, end });
+        }
+        self.check_pos(start)?;
+        self.check_pos(end)?;
+        Ok(self.get_text(Some(start), Some(end)))
+    }
+
+    //	Number of lines in the text. A text without line feeds has one line.
+    pub fn line_count(&self) -> usize {
+        let lf: usize = self.pieces.iter().map(|p| self.piece_line_feeds(p)).sum();
+        lf + 1
+    }
+
+    //	Lines in a range of zero based line numbers, without their line
+    //	endings. A line that lies within one piece is borrowed.
+    pub fn lines(&self, range: Range<usize>) -> Lines<'_> {
+        let pos = self.line_start(range.start);
+        Lines {
+            table: self,
+            index: 0,
+            piece_start: 0,
+            pos: pos.unwrap_or(self.text_len),
+            remaining: if pos.is_some() { range.len() } else { 0 },
         }
+    }
 
-        let end: usize = start + len; //  End position in the text buffer.
-        let mut txt_start = 0; //  Starting position of a piece in the buffer.
-        let mut start_so: usize = 0; //  Start of the slice in the piece its found in.
-        let mut txt_end = 0; //  End position of the current piece in the buffer.
-        let mut piece_end_pos: usize = 0; //  End position of the slice in the piece.
+    //	Lines in a range of zero based line numbers from the last to the
+    //	first, like `lines`.
+    pub fn lines_rev(&self, range: Range<usize>) -> LinesRev<'_> {
+        let stop = self
+            .line_start(range.start)
+            .filter(|_| range.start < range.end);
+        //	The last line ends before the line feed that starts the line
+        //	after the range.
+        let end = match stop {
+            Some(_) => self
+                .line_start(range.end)
+                .map_or(self.text_len, |next| next - 1),
+            None => 0,
+        };
+        let last = self.pieces.len().saturating_sub(1);
+        let last_len = self.pieces.get(last).map_or(0, |p| p.length);
+        LinesRev {
+            table: self,
+            index: last,
+            piece_start: self.text_len - last_len,
+            end,
+            stop: stop.unwrap_or(0),
+            done: stop.is_none(),
+        }
+    }
 
-        let mut piece_start: usize = 0; //  Index of the piece containing the start of the slice.
-        let mut piece_end: usize = 0; //  Index of the piece containing the end of the slice.
+    //	Absolute position of a zero based line and column. The column
+    //	is clamped to the end of the line.
+    pub fn offset_at(&self, line: usize, column: usize) -> Option<usize> {
+        let start = self.line_start(line)?;
+        let end = self.line_end(line, start);
+        Some(start + column.min(end - start))
+    }
 
-        let mut found_start = false; //  Found the start of the slice in the buffer.
-        let mut found_end = false; //  Found the end of the slice in the buffer.
+    //	Zero based line and column of an absolute position.
+    pub fn position_at(&self, offset: usize) -> Option<Position> {
+        if offset > self.text_len {
+            return None;
+        }
 
-        let piece_cnt = self.len();
+        let mut line = 0;
+        let mut line_start = 0;
+        let mut txt_end = 0;
 
-        for i in 0..piece_cnt {
-            let p = &self.pieces[i];
-            txt_start = txt_end;
+        for p in self.pieces.iter() {
+            let txt_start = txt_end;
             txt_end += p.length;
+            //	Only count the line feeds before the offset.
+            let length = p.length.min(offset - txt_start);
+            let (first, last) = self.line_start_range(p, length);
+            if last > first {
+                line += last - first;
+                let buffer_start = self.line_starts(p.buffer_index)[last - 1];
+                line_start = txt_start + buffer_start - p.start;
+            }
+            if offset <= txt_end {
+                break;
+            }
+        }
 
-            if found_start && found_end {
+        Some(Position::new(line, offset - line_start))
+    }
+
+    //	Content of a zero based line without its line ending.
+    pub fn get_line_content(&self, line: usize) -> Option<String> {
+        let start = self.line_start(line)?;
+        let end = self.line_end(line, start);
+        Some(self.get_text(Some(start), Some(end)))
+    }
+
+    //	Converts an offset counted in `unit`s into a byte offset.
+    pub fn to_byte_offset(&self, offset: usize, unit: OffsetUnit) -> Result<usize> {
+        unicode::byte_offset(self.chunks(None, None), offset, unit)
+    }
+
+    //	Converts a byte offset into an offset counted in `unit`s. The
+    //	offset has to fall on a boundary of the unit.
+    pub fn from_byte_offset(&self, offset: usize, unit: OffsetUnit) -> Result<usize> {
+        self.check_pos(offset)?;
+        let count = unicode::unit_count(self.chunks(None, Some(offset)), unit);
+        //	A cluster cut at the offset is still counted as a whole one.
+        if unit == OffsetUnit::Grapheme && self.to_byte_offset(count, unit)? != offset {
+            return Err(Error::InvalidBoundary(offset));
+        }
+        Ok(count)
+    }
+
+    //	Length of the text counted in `unit`s.
+    pub fn len_in(&self, unit: OffsetUnit) -> usize {
+        unicode::unit_count(self.chunks(None, None), unit)
+    }
+
+    //	Inserts text at a position counted in `unit`s.
+    pub fn insert_in(&mut self, txt: &str, pos: usize, unit: OffsetUnit) -> Result<()> {
+        let pos = self.to_byte_offset(pos, unit)?;
+        self.try_insert(txt, pos)
+    }
+
+    //	Deletes `length` units starting at `start`. Returns the number
+    //	of bytes removed.
+    pub fn delete_in(&mut self, start: usize, length: usize, unit: OffsetUnit) -> Result<usize> {
+        let byte_start = self.to_byte_offset(start, unit)?;
+        let byte_end = self.to_byte_offset(start + length, unit)?;
+        self.try_delete(byte_start, byte_end - byte_start)
+    }
+
+    //	Text between two positions counted in `unit`s.
+    pub fn get_text_in(
+        &self,
+        s_start: Option<usize>,
+        s_end: Option<usize>,
+        unit: OffsetUnit,
+    ) -> Result<String> {
+        let start = self.to_byte_offset(s_start.unwrap_or(0), unit)?;
+        let end = match s_end {
+            Some(end) => self.to_byte_offset(end, unit)?,
+            None => self.text_len,
+        };
+        self.try_get_text(Some(start), Some(end))
+    }
+
+    //	Absolute byte position of a zero based line and a column counted
+    //	in `unit`s. The column is clamped to the end of the line.
+    pub fn offset_at_in(&self, line: usize, column: usize, unit: OffsetUnit) -> Result<usize> {
+        let start = self.line_start(line).ok_or(Error::OutOfBounds {
+            pos: line,
+            len: self.line_count(),
+        })?;
+        let end = self.line_end(line, start);
+        match unicode::byte_offset(self.chunks(Some(start), Some(end)), column, unit) {
+            Ok(offset) => Ok(start + offset),
+            Err(Error::OutOfBounds { .. }) => Ok(end),
+            Err(Error::InvalidBoundary(_)) => Err(Error::InvalidBoundary(column)),
+            Err(err) => Err(err),
+        }
+    }
+
+    //	Zero based line and column counted in `unit`s of an absolute byte
+    //	position.
+    pub fn position_at_in(&self, offset: usize, unit: OffsetUnit) -> Result<Position> {
+        self.check_pos(offset)?;
+        let pos = self.position_at(offset).ok_or(Error::OutOfBounds {
+            pos: offset,
+            len: self.text_len,
+        })?;
+        let line_start = offset - pos.column;
+        let column = unicode::unit_count(self.chunks(Some(line_start), Some(offset)), unit);
+        Ok(Position::new(pos.line, column))
+    }
+
+    //	Absolute position of the first character in a line.
+    fn line_start(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            return Some(0);
+        }
+
+        let mut lf = 0;
+        let mut txt_end = 0;
+
+        for p in self.pieces.iter() {
+            let txt_start = txt_end;
+            txt_end += p.length;
+            let (first, last) = self.line_start_range(p, p.length);
+            if lf + last - first >= line {
+                //	The line starts after a line feed in this piece.
+                let buffer_start = self.line_starts(p.buffer_index)[first + line - lf - 1];
+                return Some(txt_start + buffer_start - p.start);
+            }
+            lf += last - first;
+        }
+
+        None
+    }
+
+    //	Absolute position of the end of a line, before its line ending.
+    fn line_end(&self, line: usize, line_start: usize) -> usize {
+        let end = match self.line_start(line + 1) {
+            //	Skip the line feed and a carriage return before it.
+            Some(next) => {
+                let end = next - 1;
+                if end > line_start && self.byte_at(end - 1) == Some(b'\r') {
+                    end - 1
+                } else {
+                    end
+                }
+            }
+            None => self.text_len,
+        };
+        end.max(line_start)
+    }
+
+    //	Byte at an absolute position.
+    fn byte_at(&self, offset: usize) -> Option<u8> {
+        let mut txt_end = 0;
+        for p in self.pieces.iter() {
+            let txt_start = txt_end;
+            txt_end += p.length;
+            if offset < txt_end {
+                let buffer = &self.buffers[p.buffer_index];
+                return Some(buffer.as_bytes()[p.start + offset - txt_start]);
+            }
+        }
+        None
+    }
+
+    //	Range of indexes into the piece's buffer line starts that fall
+    //	within the first `length` bytes of the piece.
+    fn line_start_range(&self, piece: &Piece, length: usize) -> (usize, usize) {
+        let line_starts = self.line_starts(piece.buffer_index);
+        let first = line_starts.partition_point(|&ls| ls <= piece.start);
+        let last = line_starts.partition_point(|&ls| ls <= piece.start + length);
+        (first, last)
+    }
+
+    fn line_starts(&self, buffer_index: usize) -> &[usize] {
+        self.line_starts[buffer_index]
+            .get_or_init(|| scan_line_starts(self.buffers[buffer_index].as_bytes()))
+    }
+
+    //	Offset in a piece of its first line feed at or after `from`.
+    fn next_line_feed(&self, piece: &Piece, from: usize) -> Option<usize> {
+        let line_starts = self.line_starts(piece.buffer_index);
+        let k = line_starts.partition_point(|&ls| ls <= piece.start + from);
+        line_starts
+            .get(k)
+            .filter(|&&ls| ls <= piece.start + piece.length)
+            .map(|&ls| ls - 1 - piece.start)
+    }
+
+    //	Offset in a piece of its last line feed before `to`.
+    fn prev_line_feed(&self, piece: &Piece, to: usize) -> Option<usize> {
+        let line_starts = self.line_starts(piece.buffer_index);
+        let k = line_starts.partition_point(|&ls| ls <= piece.start + to);
+        k.checked_sub(1)
+            .map(|k| line_starts[k])
+            .filter(|&ls| ls > piece.start)
+            .map(|ls| ls - 1 - piece.start)
+    }
+
+    //	Text between two positions, borrowed if it lies within one piece.
+    //	The piece at `index` starts at `piece_start`, at or before `start`.
+    fn text_from_piece(
+        &self,
+        mut index: usize,
+        mut piece_start: usize,
+        start: usize,
+        end: usize,
+    ) -> Cow<'_, str> {
+        if start == end {
+            return Cow::Borrowed("");
+        }
+        while let Some(p) = self.pieces.get(index) {
+            if start < piece_start + p.length {
                 break;
             }
+            piece_start += p.length;
+            index += 1;
+        }
+        let p = self.pieces[index];
+        if end <= piece_start + p.length {
+            let range = p.start + start - piece_start..p.start + end - piece_start;
+            return Cow::Borrowed(&self.buffers[p.buffer_index][range]);
+        }
+        let chunks = Chunks {
+            table: self,
+            index,
+            txt_end: piece_start,
+            start,
+            end,
+        };
+        Cow::Owned(chunks.collect())
+    }
+
+    //	Number of line feeds in a piece.
+    fn piece_line_feeds(&self, piece: &Piece) -> usize {
+        let (first, last) = self.line_start_range(piece, piece.length);
+        last - first
+    }
+
+    //	Owned copy of the current pieces that shares the buffers. The
+    //	snapshot is unaffected by later edits.
+    pub fn create_snapshot(&self) -> PieceTableSnapshot {
+        PieceTableSnapshot {
+            buffers: self.buffers.clone(),
+            pieces: self.pieces.clone(),
+            index: 0,
+        }
+    }
 
-            if !found_start && start >= txt_start && start < txt_end {
-                found_start = true;
-                piece_start = i;
-                let so = start - txt_start;
-                start_so = so;
+    //	Reverts the last undo step. Returns false if there was nothing
+    //	to undo.
+    pub fn undo(&mut self) -> bool {
+        if !self.history.can_undo() {
+            return false;
+        }
+        let current = HistoryEntry::new(self.pieces.clone(), self.text_len);
+        match self.history.undo(current) {
+            Some(entry) => {
+                self.restore(entry, ChangeReason::Undo);
+                true
             }
+            None => false,
+        }
+    }
 
-            if !found_end && end <= txt_end {
-                found_end = true;
-                piece_end = i;
-                let eo = txt_end - end;
-                piece_end_pos = p.length - eo;
+    //	Reapplies the last undone step. Returns false if there was
+    //	nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        if !self.history.can_redo() {
+            return false;
+        }
+        let current = HistoryEntry::new(self.pieces.clone(), self.text_len);
+        match self.history.redo(current) {
+            Some(entry) => {
+                self.restore(entry, ChangeReason::Redo);
+                true
             }
+            None => false,
         }
+    }
 
-        //  Cases For Deletion:
-        //      - Slice ends in the same piece.
-        //      - Slice contains a start and an end.
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
 
-        let mut trimmed: usize = 0;
-        let mut total: usize = 0;
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
 
-        if piece_start == piece_end {
-            trimmed = self.trim_piece(piece_start, start_so, len);
-            self.text_len -= trimmed;
-            total += trimmed;
-        } else {
-            let mut removed_offset = 0;
-            let mut init_start = false;
-
-            for i in piece_start..piece_end {
-                if !init_start && i == piece_start {
-                    let len = self[i].length;
-                    trimmed = self.trim_piece(i, start_so, len);
-                    total += trimmed;
-                    self.text_len -= trimmed;
-                    if trimmed == len {
-                        removed_offset += 1;
-                    }
-                    init_start = true;
-                } else {
-                    let pi = i - removed_offset;
-                    trimmed = self.trim_piece(pi, 0, len);
-                    total += trimmed;
-                    self.text_len -= trimmed;
-                    removed_offset += 1;
+    //	Ends the current typing run. The next edit starts a new undo step.
+    pub fn break_undo_group(&mut self) {
+        self.history.break_group();
+    }
+
+    pub fn history_depth(&self) -> usize {
+        self.history.depth()
+    }
+
+    //	Sets how many undo steps are kept. A depth of 0 disables history.
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history.set_depth(depth);
+        //	Dropped steps may have been all that used the add buffer.
+        self.apply_compaction_policy();
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.apply_compaction_policy();
+    }
+
+    fn restore(&mut self, entry: HistoryEntry, reason: ChangeReason) {
+        let old_pieces = std::mem::replace(&mut self.pieces, entry.pieces);
+        let old_len = std::mem::replace(&mut self.text_len, entry.text_len);
+        let changes = self.diff_pieces(&old_pieces, old_len).into_iter().collect();
+        self.emit(reason, changes);
+    }
+
+    //	Change from the old pieces to the current ones. Text both lists
+    //	take from the same buffer bytes at the start and the end is left
+    //	out.
+    fn diff_pieces(&self, old_pieces: &[Piece], old_len: usize) -> Option<Change> {
+        let prefix = shared_len(old_pieces.iter(), self.pieces.iter(), false);
+        if prefix == old_len && prefix == self.text_len {
+            return None;
+        }
+        let max_suffix = old_len.min(self.text_len) - prefix;
+        let suffix =
+            shared_len(old_pieces.iter().rev(), self.pieces.iter().rev(), true).min(max_suffix);
+
+        let end = old_len - suffix;
+        let new_end = self.text_len - suffix;
+        if prefix == end || prefix == new_end {
+            let removed_line_feeds = pieces_in(old_pieces, prefix, end)
+                .map(|p| self.piece_line_feeds(&p))
+                .sum();
+            let text = self.get_text(Some(prefix), Some(new_end));
+            return Some(Change::new(prefix..end, text, removed_line_feeds));
+        }
+
+        //	Compaction copies text to new buffer positions, so the pieces
+        //	can differ where the text is the same.
+        let removed: String = pieces_in(old_pieces, prefix, end)
+            .map(|p| &self.buffers[p.buffer_index][p.start..p.start + p.length])
+            .collect();
+        let inserted = self.get_text(Some(prefix), Some(new_end));
+        let mut head = removed
+            .bytes()
+            .zip(inserted.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        while !removed.is_char_boundary(head) {
+            head -= 1;
+        }
+        let max_tail = removed.len().min(inserted.len()) - head;
+        let mut tail = removed
+            .bytes()
+            .rev()
+            .zip(inserted.bytes().rev())
+            .take(max_tail)
+            .take_while(|(a, b)| a == b)
+            .count();
+        while !removed.is_char_boundary(removed.len() - tail) {
+            tail -= 1;
+        }
+        let removed = &removed[head..removed.len() - tail];
+        let inserted = &inserted[head..inserted.len() - tail];
+        let removed_line_feeds = removed.bytes().filter(|&b| b == b'\n').count();
+        let start = prefix + head;
+        Some(Change::new(
+            start..start + removed.len(),
+            inserted,
+            removed_line_feeds,
+        ))
+    }
+
+    //	Version of the text. Grows with every change, including undo
+    //	and redo.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    //	Calls the listener after every change of the text. Returns an id
+    //	to unsubscribe with.
+    pub fn subscribe<F>(&mut self, listener: F) -> ListenerId
+    where
+        F: FnMut(&ChangeEvent) + Send + 'static,
+    {
+        self.listeners.add(Box::new(listener))
+    }
+
+    //	Returns false if no listener had the id.
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        self.listeners.remove(id)
+    }
+
+    //	Adds a marker that follows the edits of the text. Offsets past
+    //	the end are clamped to it.
+    pub fn add_marker(&mut self, offset: usize, stickiness: Stickiness) -> MarkerId {
+        self.markers.add(offset.min(self.text_len), stickiness)
+    }
+
+    //	Returns false if there is no marker with the id.
+    pub fn remove_marker(&mut self, id: MarkerId) -> bool {
+        self.markers.remove(id)
+    }
+
+    //	Current offset of a marker, or None if it was removed or the
+    //	text around it was deleted.
+    pub fn marker_offset(&self, id: MarkerId) -> Option<usize> {
+        self.markers.offset(id)
+    }
+
+    //	Markers with an offset in the range, ordered by offset.
+    pub fn markers_in(&self, range: Range<usize>) -> Vec<(MarkerId, usize)> {
+        self.markers.in_range(range)
+    }
+
+    pub fn marker_count(&self) -> usize {
+        self.markers.len()
+    }
+
+    //	Markers deleted with their surrounding text since the last call.
+    pub fn take_deleted_markers(&mut self) -> Vec<MarkerId> {
+        self.markers.take_deleted()
+    }
+
+    //	Adds a decoration that follows the edits of the text. The range
+    //	is clamped to the text.
+    pub fn add_decoration(
+        &mut self,
+        range: Range<usize>,
+        class: &str,
+        stickiness: RangeStickiness,
+    ) -> DecorationId {
+        let start = range.start.min(self.text_len);
+        let end = range.end.clamp(start, self.text_len);
+        self.decorations.add(start..end, class, stickiness)
+    }
+
+    //	Returns false if there is no decoration with the id.
+    pub fn remove_decoration(&mut self, id: DecorationId) -> bool {
+        self.decorations.remove(id)
+    }
+
+    pub fn decoration(&self, id: DecorationId) -> Option<Decoration> {
+        self.decorations.get(id)
+    }
+
+    pub fn decoration_count(&self) -> usize {
+        self.decorations.len()
+    }
+
+    //	Decorations that overlap or touch an offset range, ordered by
+    //	start.
+    pub fn decorations_in(&self, range: Range<usize>) -> Vec<Decoration> {
+        self.decorations.search(range)
+    }
+
+    //	Decorations on a range of zero based lines, ordered by start.
+    pub fn decorations_in_lines(&self, lines: Range<usize>) -> Vec<Decoration> {
+        if lines.is_empty() {
+            return Vec::new();
+        }
+        let Some(start) = self.line_start(lines.start) else {
+            return Vec::new();
+        };
+        //	The last line ends at its line feed.
+        let end = self
+            .line_start(lines.end)
+            .map_or(self.text_len, |next| next - 1);
+        self.decorations.search(start..end)
+    }
+
+    //	Bumps the version, moves the markers and decorations and tells
+    //	the listeners about the changes.
+    fn emit(&mut self, reason: ChangeReason, mut changes: Vec<Change>) {
+        changes.retain(|c| !c.range.is_empty() || !c.text.is_empty());
+        if changes.is_empty() {
+            return;
+        }
+        self.version += 1;
+        self.search_text.take();
+        for change in changes.iter() {
+            self.markers.apply(change);
+            self.decorations.apply(change);
+        }
+        if !self.listeners.is_empty() {
+            let event = ChangeEvent {
+                version: self.version,
+                reason,
+                changes,
+            };
+            self.listeners.notify(&event);
+        }
+        self.apply_compaction_policy();
+    }
+
+    pub fn compaction_policy(&self) -> CompactionPolicy {
+        self.compaction
+    }
+
+    pub fn set_compaction_policy(&mut self, policy: CompactionPolicy) {
+        self.compaction = policy;
+        self.next_compaction = 0;
+        self.apply_compaction_policy();
+    }
+
+    //	Share of the add buffers that is not part of the text.
+    pub fn garbage_ratio(&self) -> f64 {
+        let add_len: usize = (self.orig_buffers..self.buffers.len())
+            .map(|i| self.get_buffer_len(i))
+            .sum();
+        if add_len == 0 {
+            return 0.0;
+        }
+        let live: usize = self
+            .pieces
+            .iter()
+            .filter(|p| p.buffer_index >= self.orig_buffers)
+            .map(|p| p.length)
+            .sum();
+        1.0 - live.min(add_len) as f64 / add_len as f64
+    }
+
+    //	Merges pieces that follow each other in a buffer and copies runs
+    //	of small pieces into one piece. The buffers only grow, so undo
+    //	steps keep pointing at valid text.
+    pub fn compact(&mut self) {
+        let clean = !self.is_dirty();
+        self.merge_pieces();
+        self.rewrite_fragments();
+        if clean {
+            self.save_point = self.pieces.clone();
+        }
+    }
+
+    fn apply_compaction_policy(&mut self) {
+        let policy = self.compaction;
+        if let Some(max_pieces) = policy.max_pieces {
+            if self.pieces.len() > max_pieces.max(self.next_compaction) {
+                self.compact();
+                //	Pieces that are already large stay. Waiting for the
+                //	count to double keeps compaction amortized.
+                self.next_compaction = self.pieces.len() * 2;
+            }
+        }
+        if let Some(max_ratio) = policy.max_garbage_ratio {
+            let no_history = !self.history.can_undo() && !self.history.can_redo();
+            if no_history && self.garbage_ratio() > max_ratio {
+                self.flatten();
+            }
+        }
+    }
+
+    //	Joins pieces where one ends where the next starts in the same
+    //	buffer and drops empty pieces.
+    fn merge_pieces(&mut self) {
+        let mut merged: Vec<Piece> = Vec::with_capacity(self.pieces.len());
+        for p in self.pieces.iter().filter(|p| p.length > 0) {
+            match merged.last_mut() {
+                Some(last)
+                    if last.buffer_index == p.buffer_index
+                        && last.start + last.length == p.start =>
+                {
+                    last.length += p.length;
                 }
+                _ => merged.push(*p),
             }
+        }
+        if merged.is_empty() {
+            merged.push(Piece::new(0, 0, 0));
+        }
+        self.pieces = merged;
+    }
 
-            trimmed = self.trim_piece(piece_end - removed_offset, 0, piece_end_pos);
-            total += trimmed;
-            self.text_len -= trimmed;
+    //	Copies every run of at least two small pieces to the end of the
+    //	add buffer and replaces the run with one piece.
+    fn rewrite_fragments(&mut self) {
+        let small = self.compaction.small_piece_len;
+        let old_pieces = std::mem::take(&mut self.pieces);
+        let mut pieces = Vec::with_capacity(old_pieces.len());
+        let mut i = 0;
+        while i < old_pieces.len() {
+            let run = old_pieces[i..]
+                .iter()
+                .take_while(|p| p.length < small)
+                .count();
+            if run < 2 {
+                pieces.push(old_pieces[i]);
+                i += 1;
+                continue;
+            }
+            let txt: String = old_pieces[i..i + run]
+                .iter()
+                .map(|p| &self.buffers[p.buffer_index][p.start..p.start + p.length])
+                .collect();
+            pieces.push(self.append_to_add_buffer(&txt));
+            i += run;
         }
+        self.pieces = pieces;
     }
 
-    //	Start and end are absolute positions in the buffer.
-    pub fn get_text(&self, s_start: Option<usize>, s_end: Option<usize>) -> String {
-        //	Locate the starting piece and append it's sliced
-        //	text. Then find the end piece and append it's text.
-        let mut ret = String::new();
-        //	Initialize optional args.
-        let mut start: usize = 0;
-        let mut end: usize = 0;
-        let mut has_end = false;
-        if s_end.is_some() {
-            end = s_end.unwrap();
-            has_end = true;
+    //	Reloads the current text into new buffers. Only valid while no
+    //	undo step points into the old buffers.
+    fn flatten(&mut self) {
+        let clean = !self.is_dirty();
+        self.lossy = self.is_lossy();
+        let txt = self.get_text(None, None);
+        self.load_chunks(chunks_of(&txt).into_iter().map(Buffer::from).collect());
+        //	The old save point points into the replaced buffers.
+        if !clean {
+            self.save_point = Vec::new();
+        }
+    }
+}
+
+//	Parts of the pieces between two positions of their text.
+fn pieces_in(pieces: &[Piece], start: usize, end: usize) -> impl Iterator<Item = Piece> + '_ {
+    let mut piece_start = 0;
+    pieces.iter().filter_map(move |p| {
+        let piece_end = piece_start + p.length;
+        let from = start.max(piece_start) - piece_start;
+        let to = end.min(piece_end).max(piece_start) - piece_start;
+        piece_start = piece_end;
+        (from < to).then(|| Piece::new(p.buffer_index, p.start + from, to - from))
+    })
+}
+
+//	Number of bytes at the start of two piece lists that come from the
+//	same buffer positions. With `from_end` the lists are reversed and
+//	the pieces are compared by their ends.
+fn shared_len<'a, I>(mut old: I, mut new: I, from_end: bool) -> usize
+where
+    I: Iterator<Item = &'a Piece>,
+{
+    let (mut a, mut b) = (old.next(), new.next());
+    //	Bytes already consumed from the current pieces.
+    let (mut a_off, mut b_off) = (0, 0);
+    let mut len = 0;
+    while let (Some(pa), Some(pb)) = (a, b) {
+        if a_off == pa.length {
+            (a, a_off) = (old.next(), 0);
+            continue;
         }
-        if s_start.is_some() {
-            start = s_start.unwrap();
+        if b_off == pb.length {
+            (b, b_off) = (new.next(), 0);
+            continue;
         }
+        let (pos_a, pos_b) = if from_end {
+            (pa.start + pa.length - a_off, pb.start + pb.length - b_off)
+        } else {
+            (pa.start + a_off, pb.start + b_off)
+        };
+        if pa.buffer_index != pb.buffer_index || pos_a != pos_b {
+            break;
+        }
+        let n = (pa.length - a_off).min(pb.length - b_off);
+        a_off += n;
+        b_off += n;
+        len += n;
+    }
+    len
+}
+
+//	Sequential position in a piece list used to rebuild it in one pass.
+struct PieceCursor<'a> {
+    pieces: &'a [Piece],
+    //	Index of the current piece.
+    index: usize,
+    //	Start position of the current piece in the text.
+    piece_start: usize,
+    //	Position in the text.
+    pos: usize,
+}
 
-        //	Make sure start and end were initialized properly.
-        if has_end && start >= end {
-            return ret;
+impl<'a> PieceCursor<'a> {
+    fn new(pieces: &'a [Piece]) -> Self {
+        Self {
+            pieces,
+            index: 0,
+            piece_start: 0,
+            pos: 0,
         }
+    }
 
-        //	Text buffer positions for the current slice.
-        let mut txt_start = 0;
-        let mut txt_end = 0;
+    //	Moves to `target` and passes the slice of each piece on the way.
+    fn advance<F: FnMut(Piece)>(&mut self, target: usize, mut f: F) {
+        while self.pos < target {
+            let Some(p) = self.pieces.get(self.index) else {
+                break;
+            };
+            let piece_end = self.piece_start + p.length;
+            let from = self.pos - self.piece_start;
+            let to = piece_end.min(target) - self.piece_start;
+            if to > from {
+                f(Piece::new(p.buffer_index, p.start + from, to - from));
+            }
+            self.pos = self.piece_start + to;
+            if self.pos == piece_end {
+                self.index += 1;
+                self.piece_start = piece_end;
+            }
+        }
+    }
 
-        //	Iterate over pieces until you print the substring.
-        for i in 0..self.len() {
-            let ip = &self[i];
+    fn copy_until(&mut self, target: usize, out: &mut Vec<Piece>) {
+        self.advance(target, |p| out.push(p));
+    }
+
+    fn skip_until<F: FnMut(Piece)>(&mut self, target: usize, f: F) {
+        self.advance(target, f);
+    }
+}
+
+//	Borrowed slices of the text, one per piece.
+pub struct Chunks<'a> {
+    table: &'a PieceTable,
+    //	Index of the next piece.
+    index: usize,
+    //	End position of the previous piece in the text.
+    txt_end: usize,
+    start: usize,
+    end: usize,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(ip) = self.table.pieces.get(self.index) {
             //	Set start to previous end and increment the end
             //	by the piece length.
-            txt_start = txt_end;
-            txt_end += ip.length;
-            let buffer = (!ip.is_orig) as usize;
-
-            //	Cases:
-            //	Piece contains start and end
-            if start >= txt_start && (has_end && end <= txt_end) {
-                let start_offset = start - txt_start;
-                let end_offset = txt_end - end;
-                ret.push_str(
-                    self.get_piece_text(buffer, i, start_offset, end_offset)
-                        .as_str(),
-                );
+            let txt_start = self.txt_end;
+            self.txt_end += ip.length;
+            self.index += 1;
+
+            //	Piece is after the end, or the range is empty.
+            if txt_start >= self.end || self.start >= self.end {
                 break;
             }
-            //	Piece contains start
-            else if start >= txt_start {
-                let start_offset = start - txt_start;
-                ret.push_str(self.get_piece_text(buffer, i, start_offset, 0).as_str());
+            //	Piece is before the start or empty.
+            if self.txt_end <= self.start {
+                continue;
             }
-            //	Piece contains end
-            else if has_end && end <= txt_end {
-                let end_offset = start - txt_end;
-                ret.push_str(self.get_piece_text(buffer, i, 0, end_offset).as_str());
+
+            //	Trim the piece if it contains the start or the end.
+            let start_offset = self.start.saturating_sub(txt_start);
+            let end_offset = self.txt_end.saturating_sub(self.end);
+            let buffer = &self.table.buffers[ip.buffer_index];
+            return Some(&buffer[ip.start + start_offset..ip.start + ip.length - end_offset]);
+        }
+        self.index = self.table.pieces.len();
+        None
+    }
+}
+
+//	Lines of a piece table from the first to the last. See
+//	`PieceTable::lines`.
+pub struct Lines<'a> {
+    table: &'a PieceTable,
+    //	Piece at or before the start of the next line and its position
+    //	in the text.
+    index: usize,
+    piece_start: usize,
+    //	Start of the next line.
+    pos: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let table = self.table;
+
+        //	Find the line feed that ends the line.
+        let (mut index, mut piece_start) = (self.index, self.piece_start);
+        let mut line_feed = None;
+        while let Some(p) = table.pieces.get(index) {
+            let from = self.pos.max(piece_start) - piece_start;
+            if let Some(lf) = table.next_line_feed(p, from).filter(|_| from < p.length) {
+                line_feed = Some(piece_start + lf);
                 break;
             }
-            //	Piece contains neither
-            else {
-                ret.push_str(self.get_piece_text(buffer, i, 0, 0).as_str());
+            piece_start += p.length;
+            index += 1;
+        }
+
+        let start = self.pos;
+        let end = line_feed.unwrap_or(table.text_len);
+        let line = table.text_from_piece(self.index, self.piece_start, start, end);
+        match line_feed {
+            Some(lf) => {
+                self.pos = lf + 1;
+                (self.index, self.piece_start) = (index, piece_start);
+                Some(strip_cr(line))
+            }
+            //	The last line has no line ending.
+            None => {
+                self.remaining = 0;
+                Some(line)
             }
         }
-        ret
     }
+}
+
+//	Lines of a piece table from the last to the first. See
+//	`PieceTable::lines_rev`.
+pub struct LinesRev<'a> {
+    table: &'a PieceTable,
+    //	Piece at or before the end of the next line and its position in
+    //	the text.
+    index: usize,
+    piece_start: usize,
+    //	End of the next line, before its line ending.
+    end: usize,
+    //	Start of the first line of the range.
+    stop: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for LinesRev<'a> {
+    type Item = Cow<'a, str>;
 
-    fn get_pos_piece(&self, char_index: usize) -> Option<usize> {
-        for n in 0..self.pieces.len() {
-            let p = self.pieces.get(n).unwrap();
-            let end = p.start + p.length;
-            if char_index <= end {
-                return Some(n);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let table = self.table;
+
+        //	Find the line feed that ends the previous line.
+        let (mut index, mut piece_start) = (self.index, self.piece_start);
+        let mut start = 0;
+        while let Some(p) = table.pieces.get(index) {
+            let to = self
+                .end
+                .min(piece_start + p.length)
+                .saturating_sub(piece_start);
+            if let Some(lf) = table.prev_line_feed(p, to) {
+                start = piece_start + lf + 1;
+                break;
+            }
+            if index == 0 {
+                break;
             }
+            index -= 1;
+            piece_start -= table.pieces[index].length;
         }
-        None
+
+        let line = table.text_from_piece(index, piece_start, start, self.end);
+        let has_line_feed = self.end < table.text_len;
+        if start <= self.stop {
+            self.done = true;
+        } else {
+            self.end = start - 1;
+            (self.index, self.piece_start) = (index, piece_start);
+        }
+        Some(if has_line_feed { strip_cr(line) } else { line })
     }
+}
 
-    fn get_piece_text(
-        &self,
-        buffer_index: usize,
-        piece_index: usize,
-        start_offset: usize,
-        end_offset: usize,
-    ) -> String {
-        let buffer = &self.buffers[buffer_index];
-        let piece = self[piece_index];
-        let ps = piece.start;
-        let pl = piece.length;
-        let start = piece.start + start_offset;
-        let end = piece.length - end_offset + start;
-        self.get_buffer_slice(buffer, start, end)
+//	Removes the carriage return of a CRLF line ending.
+fn strip_cr(line: Cow<'_, str>) -> Cow<'_, str> {
+    match line {
+        Cow::Borrowed(txt) => Cow::Borrowed(txt.strip_suffix('\r').unwrap_or(txt)),
+        Cow::Owned(mut txt) => {
+            if txt.ends_with('\r') {
+                txt.pop();
+            }
+            Cow::Owned(txt)
+        }
     }
+}
 
-    fn merge_pieces(&mut self) {
-        let new_orig = self.get_text(None, None);
-        let new_pt = PieceTable::new(&new_orig);
-        self.buffers = new_pt.buffers;
-        self.pieces = new_pt.pieces;
+//	Immutable version of a piece table. Reads one piece at a time.
+pub struct PieceTableSnapshot {
+    buffers: Vec<Arc<Buffer>>,
+    pieces: Vec<Piece>,
+    index: usize,
+}
+
+impl TextSnapshot for PieceTableSnapshot {
+    fn read(&mut self) -> Option<Box<str>> {
+        while let Some(piece) = self.pieces.get(self.index) {
+            self.index += 1;
+            if piece.length > 0 {
+                let buffer = &self.buffers[piece.buffer_index];
+                return Some(buffer[piece.start..piece.start + piece.length].into());
+            }
+        }
+        None
+    }
+}
+
+//	Writes the text one piece at a time.
+impl fmt::Display for PieceTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chunks(None, None)
+            .try_for_each(|chunk| f.write_str(chunk))
     }
 }
 
@@ -1,74 +1,174 @@
-use std::{default, sync::Arc};
+use std::sync::Arc;
 
-const AVERAGE_BUFFER_SIZE: usize = 665535;
+pub use crate::textbuffer::TextSnapshot;
+use crate::{textbuffer::Position, Error, Result};
 
+pub(crate) const AVERAGE_BUFFER_SIZE: usize = 665535;
+
+//	Line starts of a buffer. Readonly buffers use the narrowest type
+//	that fits, the change buffer uses a growable array.
 pub enum UintArray {
-    UintArray(Arc<[usize]>),
+    UintArray(Vec<usize>),
     Uint32Array(Arc<[u32]>),
     Uint16Array(Arc<[u16]>),
 }
 
+impl UintArray {
+    pub fn len(&self) -> usize {
+        match self {
+            UintArray::UintArray(arr) => arr.len(),
+            UintArray::Uint32Array(arr) => arr.len(),
+            UintArray::Uint16Array(arr) => arr.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> usize {
+        match self {
+            UintArray::UintArray(arr) => arr[index],
+            UintArray::Uint32Array(arr) => arr[index] as usize,
+            UintArray::Uint16Array(arr) => arr[index] as usize,
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<usize> {
+        match self {
+            UintArray::UintArray(arr) => arr.clone(),
+            _ => (0..self.len()).map(|i| self.get(i)).collect(),
+        }
+    }
+
+    //	Appends a value. Readonly arrays are widened into a growable one.
+    pub fn push(&mut self, value: usize) {
+        if let UintArray::UintArray(arr) = self {
+            arr.push(value);
+            return;
+        }
+        let mut arr = self.to_vec();
+        arr.push(value);
+        *self = UintArray::UintArray(arr);
+    }
+}
+
+//	Stores sorted line starts in the narrowest array that fits the
+//	last value.
 pub fn create_usize_array(arr: Vec<usize>) -> UintArray {
-    //  Check the last character
-    //  If the last character is a 16 bit number create a u16 array.
-    //  Else create a u32 array.
-    //  Set the first value in the new array to 0.
-    todo!()
+    match arr.last() {
+        Some(&last) if last > u32::MAX as usize => UintArray::UintArray(arr),
+        Some(&last) if last > u16::MAX as usize => {
+            UintArray::Uint32Array(arr.into_iter().map(|v| v as u32).collect())
+        }
+        _ => UintArray::Uint16Array(arr.into_iter().map(|v| v as u16).collect()),
+    }
 }
 
 pub struct LineStarts {
-    pub line_starts: Vec<usize>, 
-    pub cr: usize, 
-    pub lf: usize, 
-    pub crlf: usize, 
-    pub is_basic_ascii: bool
+    pub line_starts: UintArray,
+    pub cr: usize,
+    pub lf: usize,
+    pub crlf: usize,
+    pub is_basic_ascii: bool,
 }
 
 impl LineStarts {
-    fn new(line_starts: Vec<usize>, cr: usize, lf: usize, crlf: usize, is_basic_ascii: bool) -> Self {
-        LineStarts{ line_starts, cr, lf, crlf, is_basic_ascii }
+    fn new(
+        line_starts: UintArray,
+        cr: usize,
+        lf: usize,
+        crlf: usize,
+        is_basic_ascii: bool,
+    ) -> Self {
+        LineStarts {
+            line_starts,
+            cr,
+            lf,
+            crlf,
+            is_basic_ascii,
+        }
     }
 }
 
-pub fn create_line_starts(r: Vec<usize>, str: Box<str>) -> LineStarts {
-    //  Iterate over the string and keep count of carriage returns, line feeds, and
-    //  the combination.
+//	Scans a string for line starts and counts its line endings. `r` is
+//	reused as the storage for the line starts.
+//
+//	Lines end after a line feed, so a lone carriage return is counted
+//	in `cr` but does not start a new line.
+pub fn create_line_starts(r: Vec<usize>, str: &str) -> LineStarts {
+    let mut r = r;
+    r.clear();
+    r.push(0);
+    let (mut cr, mut lf, mut crlf) = (0, 0, 0);
+    let mut is_basic_ascii = true;
 
-    //  If a character is not a tab or outside of regular ascii bounds
-    //  set is_basic_ascii false.
+    let bytes = str.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => {
+                crlf += 1;
+                r.push(i + 2);
+                i += 1;
+            }
+            b'\r' => cr += 1,
+            b'\n' => {
+                lf += 1;
+                r.push(i + 1);
+            }
+            //	Anything other than a tab or printable ascii.
+            chr if is_basic_ascii && chr != b'\t' && !(32..=126).contains(&chr) => {
+                is_basic_ascii = false;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    LineStarts::new(create_usize_array(r), cr, lf, crlf, is_basic_ascii)
+}
 
-    //  Create and return a LineStarts struct
-    todo!()
+//	Scans a string for line starts only. Readonly buffers get the
+//	narrowest array, otherwise a growable one is returned.
+pub fn create_line_starts_fast(str: &str, readonly: bool) -> UintArray {
+    let r = scan_line_starts(str.as_bytes());
+    if readonly {
+        create_usize_array(r)
+    } else {
+        UintArray::UintArray(r)
+    }
 }
 
-pub fn create_line_starts_fast(str: Box<str>, readonly: bool) -> UintArray {
-    //  Iterate over the characters in the string and keep track of general
-    //  returns.
-    //  If its readonly return a Uint16Array or Uint32Array.
-    //  Otherwise a UintArray.
-    todo!()
+//	Positions following each line feed in the bytes. The first line
+//	always starts at 0.
+pub(crate) fn scan_line_starts(bytes: &[u8]) -> Vec<usize> {
+    let mut line_starts = vec![0];
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'\n' {
+            line_starts.push(i + 1);
+        }
+    }
+    line_starts
 }
 
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum NodeColor {
+    #[default]
     Black = 0,
     Red = 1,
 }
 
-impl Default for NodeColor {
-    fn default() -> Self {
-        NodeColor::Black
-    }
-}
-
 pub struct NodePosition {
     //  Piece index
-    node: TreeNode,
+    node: Link,
     //  Remainder in currrent piece.
     remainder: usize,
     //  Node start offset in document.
     node_start_offset: usize,
 }
 
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
 pub struct BufferCursor {
     //  The line number in the current buffer.
     line: usize,
@@ -76,6 +176,13 @@ pub struct BufferCursor {
     column: usize,
 }
 
+impl BufferCursor {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Piece {
     buffer_index: usize,
     start: BufferCursor,
@@ -85,56 +192,1062 @@ pub struct Piece {
 }
 
 impl Piece {
-    pub fn new(buffer_index: usize, start: BufferCursor, end: BufferCursor, length: usize, line_feed_cnt: usize) -> Self{
-        Self{ buffer_index, start, end, length, line_feed_cnt }
+    pub fn new(
+        buffer_index: usize,
+        start: BufferCursor,
+        end: BufferCursor,
+        length: usize,
+        line_feed_cnt: usize,
+    ) -> Self {
+        Self {
+            buffer_index,
+            start,
+            end,
+            length,
+            line_feed_cnt,
+        }
     }
 }
 
-pub trait TextSnapshot {
-    fn read() -> Option<Box<str>>;
-}
-
 pub struct StringBuffer {
-    buffer: String,
+    //	Shared with snapshots of the tree.
+    buffer: Arc<String>,
     line_starts: UintArray,
 }
 
 impl StringBuffer {
     pub fn new(buffer: String, line_starts: UintArray) -> Self {
-        Self { buffer, line_starts }
+        Self {
+            buffer: Arc::new(buffer),
+            line_starts,
+        }
     }
 }
 
-pub type Link = Option<Box<TreeNode>>;
+//	Index of a node in the tree's node arena.
+pub type Link = usize;
 
-#[derive(Default)]
+//	Every leaf points to the sentinel. It is always black and lives at
+//	index 0 of the node arena.
+pub const SENTINEL: Link = 0;
+
+#[derive(Default, Clone, Copy)]
 pub struct TreeNode {
     parent: Link,
     left: Link,
     right: Link,
     color: NodeColor,
 
-    piece: Option<Piece>,
+    piece: Piece,
+    //	Length of the text in the left subtree.
     size_left: usize,
+    //	Line feeds in the left subtree.
     line_feeds_left: usize,
 }
 
 impl TreeNode {
-    pub fn new(piece: Option<Piece>, color: NodeColor) -> Self {
-        Self { piece, color, size_left: 0, line_feeds_left: 0, parent: None, left: None, right: None }
+    pub fn new(piece: Piece, color: NodeColor) -> Self {
+        Self {
+            piece,
+            color,
+            size_left: 0,
+            line_feeds_left: 0,
+            parent: SENTINEL,
+            left: SENTINEL,
+            right: SENTINEL,
+        }
+    }
+}
+
+//	Red-black tree of pieces. Buffer 0 is the change buffer and the
+//	remaining buffers hold the original text. Each node caches the
+//	length and line feeds of its left subtree so offset and line
+//	lookups are O(log n).
+pub struct PieceTree {
+    buffers: Vec<StringBuffer>,
+    nodes: Vec<TreeNode>,
+    //	Arena slots of detached nodes that can be reused.
+    free: Vec<Link>,
+    root: Link,
+}
+
+impl PieceTree {
+    pub fn new(orig_txt: &str) -> Self {
+        let mut pt = Self {
+            buffers: vec![StringBuffer::new(
+                String::new(),
+                UintArray::UintArray(vec![0]),
+            )],
+            nodes: vec![TreeNode::default()],
+            free: Vec::new(),
+            root: SENTINEL,
+        };
+        if !orig_txt.is_empty() {
+            let line_starts = create_line_starts_fast(orig_txt, true);
+            let end_line = line_starts.len() - 1;
+            let end = BufferCursor::new(end_line, orig_txt.len() - line_starts.get(end_line));
+            pt.buffers
+                .push(StringBuffer::new(orig_txt.to_string(), line_starts));
+            let piece = Piece::new(1, BufferCursor::default(), end, orig_txt.len(), end_line);
+            pt.rb_insert_left(SENTINEL, piece);
+        }
+        pt
+    }
+
+    //	Number of pieces in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.free.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root == SENTINEL
+    }
+
+    //	Length of the text in bytes.
+    pub fn text_len(&self) -> usize {
+        let mut len = 0;
+        let mut x = self.root;
+        while x != SENTINEL {
+            len += self.nodes[x].size_left + self.nodes[x].piece.length;
+            x = self.nodes[x].right;
+        }
+        len
+    }
+
+    //	Number of lines in the text. A text without line feeds has one line.
+    pub fn line_count(&self) -> usize {
+        let mut lf = 0;
+        let mut x = self.root;
+        while x != SENTINEL {
+            lf += self.nodes[x].line_feeds_left + self.nodes[x].piece.line_feed_cnt;
+            x = self.nodes[x].right;
+        }
+        lf + 1
+    }
+
+    //	Inserts text at a position. Positions past the end of the text
+    //	append to it.
+    //
+    //	Panics if the position splits a character. Use `try_insert` to
+    //	get an error instead.
+    pub fn insert(&mut self, txt: &str, pos: usize) {
+        let pos = pos.min(self.text_len());
+        self.try_insert(txt, pos).expect("Error inserting text.");
     }
 
-    pub fn next() -> Option<Self> {
-        todo!()
+    pub fn try_insert(&mut self, txt: &str, pos: usize) -> Result<()> {
+        self.check_pos(pos)?;
+        if txt.is_empty() {
+            return Ok(());
+        }
+        let piece = self.append_change(txt);
+
+        if self.root == SENTINEL {
+            self.rb_insert_left(SENTINEL, piece);
+            return Ok(());
+        }
+
+        let np = self.node_at(pos);
+        let node = np.node;
+        let node_len = self.nodes[node].piece.length;
+        if np.remainder == node_len {
+            self.rb_insert_right(node, piece);
+        } else if np.remainder == 0 {
+            self.rb_insert_left(node, piece);
+        } else {
+            //	Split the node and insert the new piece inbetween.
+            let right = self.shrink_node(node, np.remainder);
+            let new_node = self.rb_insert_right(node, piece);
+            self.rb_insert_right(new_node, right);
+        }
+        Ok(())
     }
 
-    pub fn prev() -> Option<Self> {
-        todo!()
+    //	Deletes `length` bytes starting at `start`. The length is clamped
+    //	to the end of the text.
+    //
+    //	Panics if the range splits a character. Use `try_delete` to get
+    //	an error instead.
+    pub fn delete(&mut self, start: usize, length: usize) {
+        let text_len = self.text_len();
+        if start >= text_len {
+            return;
+        }
+        let len = length.min(text_len - start);
+        self.try_delete(start, len).expect("Error deleting text.");
     }
 
-    pub fn detach() -> () {
-        
+    //	Returns the number of bytes removed.
+    pub fn try_delete(&mut self, start: usize, length: usize) -> Result<usize> {
+        let end = start.checked_add(length).ok_or(Error::OutOfBounds {
+            pos: usize::MAX,
+            len: self.text_len(),
+        })?;
+        self.check_pos(start)?;
+        self.check_pos(end)?;
+        if length == 0 {
+            return Ok(0);
+        }
+
+        //	Make both ends of the range fall on node boundaries and
+        //	remove every node inbetween.
+        self.split(start);
+        self.split(end);
+
+        let mut removed = Vec::new();
+        let mut node = self.node_starting_at(start);
+        let mut size = 0;
+        while node != SENTINEL && start + size < end {
+            removed.push(node);
+            size += self.nodes[node].piece.length;
+            node = self.next(node);
+        }
+        for node in removed {
+            self.rb_delete(node);
+        }
+        Ok(length)
     }
+
+    //	Removes `length` bytes from a piece starting at `start_offset`.
+    //	Pieces are indexed in text order.
+    pub fn trim_piece(&mut self, piece_index: usize, start_offset: usize, length: usize) -> usize {
+        let mut node = self.leftest(self.root);
+        for _ in 0..piece_index {
+            node = self.next(node);
+        }
+        if node == SENTINEL {
+            return 0;
+        }
+        let piece_len = self.nodes[node].piece.length;
+        let start_offset = start_offset.min(piece_len);
+        let len = length.min(piece_len - start_offset);
+        let offset = self.offset_of_node(node);
+        self.delete(offset + start_offset, len);
+        len
+    }
+
+    //	Start and end are absolute positions in the buffer.
+    //
+    //	Panics if start or end split a character. Use `try_get_text` to
+    //	get an error instead.
+    pub fn get_text(&self, s_start: Option<usize>, s_end: Option<usize>) -> String {
+        let mut ret = String::new();
+        let start = s_start.unwrap_or(0);
+        let end = s_end.unwrap_or(usize::MAX).min(self.text_len());
+        if start >= end {
+            return ret;
+        }
+
+        let np = self.node_at(start);
+        let mut node = np.node;
+        let mut txt_start = np.node_start_offset;
+        while node != SENTINEL && txt_start < end {
+            let piece = self.nodes[node].piece;
+            let txt_end = txt_start + piece.length;
+            let start_offset = start.saturating_sub(txt_start);
+            let end_offset = txt_end.saturating_sub(end);
+            let buffer = &self.buffers[piece.buffer_index].buffer;
+            let piece_start = self.offset_in_buffer(piece.buffer_index, piece.start);
+            ret.push_str(
+                &buffer[piece_start + start_offset..piece_start + piece.length - end_offset],
+            );
+            txt_start = txt_end;
+            node = self.next(node);
+        }
+        ret
+    }
+
+    pub fn try_get_text(&self, s_start: Option<usize>, s_end: Option<usize>) -> Result<String> {
+        let start = s_start.unwrap_or(0);
+        let end = s_end.unwrap_or(self.text_len());
+        if start > end {
+            return Err(Error::InvalidRange { start, end });
+        }
+        self.check_pos(start)?;
+        self.check_pos(end)?;
+        Ok(self.get_text(Some(start), Some(end)))
+    }
+
+    //	Whether a position is in the text and not inside a character.
+    pub fn is_char_boundary(&self, pos: usize) -> bool {
+        match self.byte_at(pos) {
+            //	UTF-8 continuation bytes are 0b10xxxxxx.
+            Some(b) => (b as i8) >= -0x40,
+            None => pos == self.text_len(),
+        }
+    }
+
+    fn check_pos(&self, pos: usize) -> Result<()> {
+        let len = self.text_len();
+        if pos > len {
+            return Err(Error::OutOfBounds { pos, len });
+        }
+        if !self.is_char_boundary(pos) {
+            return Err(Error::InvalidBoundary(pos));
+        }
+        Ok(())
+    }
+
+    //	Byte at an absolute position.
+    fn byte_at(&self, offset: usize) -> Option<u8> {
+        let node = self.node_starting_at(offset);
+        if node == SENTINEL {
+            return None;
+        }
+        let piece = self.nodes[node].piece;
+        let remainder = offset - self.offset_of_node(node);
+        let start = self.offset_in_buffer(piece.buffer_index, piece.start);
+        Some(self.buffers[piece.buffer_index].buffer.as_bytes()[start + remainder])
+    }
+
+    //	Owned copy of the current pieces in text order that shares the
+    //	buffers. The snapshot is unaffected by later edits.
+    pub fn create_snapshot(&self) -> PieceTreeSnapshot {
+        let mut pieces = Vec::with_capacity(self.len());
+        let mut node = self.leftest(self.root);
+        while node != SENTINEL {
+            let piece = self.nodes[node].piece;
+            let start = self.offset_in_buffer(piece.buffer_index, piece.start);
+            pieces.push((piece.buffer_index, start, piece.length));
+            node = self.next(node);
+        }
+        PieceTreeSnapshot {
+            buffers: self.buffers.iter().map(|b| b.buffer.clone()).collect(),
+            pieces,
+            index: 0,
+        }
+    }
+
+    //	Absolute position of a zero based line and column. The column
+    //	is clamped to the end of the line.
+    pub fn offset_at(&self, line: usize, column: usize) -> Option<usize> {
+        let start = self.line_start(line)?;
+        let end = self.line_end(line, start);
+        Some(start + column.min(end - start))
+    }
+
+    //	Zero based line and column of an absolute position.
+    pub fn position_at(&self, offset: usize) -> Option<Position> {
+        if offset > self.text_len() {
+            return None;
+        }
+        let line = self.line_feeds_before(offset);
+        let line_start = self.line_start(line)?;
+        Some(Position::new(line, offset - line_start))
+    }
+
+    //	Content of a zero based line without its line ending.
+    pub fn get_line_content(&self, line: usize) -> Option<String> {
+        let start = self.line_start(line)?;
+        let end = self.line_end(line, start);
+        Some(self.get_text(Some(start), Some(end)))
+    }
+
+    //	Absolute position of the first character in a line.
+    fn line_start(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            return Some(0);
+        }
+        if line >= self.line_count() {
+            return None;
+        }
+
+        let mut x = self.root;
+        let mut line = line;
+        let mut left_len = 0;
+        while x != SENTINEL {
+            let node = &self.nodes[x];
+            if node.left != SENTINEL && node.line_feeds_left >= line {
+                x = node.left;
+            } else if node.line_feeds_left + node.piece.line_feed_cnt >= line {
+                //	The line starts after a line feed in this piece.
+                let index = line - node.line_feeds_left - 1;
+                return Some(left_len + node.size_left + self.accumulated_value(x, index));
+            } else {
+                line -= node.line_feeds_left + node.piece.line_feed_cnt;
+                left_len += node.size_left + node.piece.length;
+                x = node.right;
+            }
+        }
+        None
+    }
+
+    //	Absolute position of the end of a line, before its line ending.
+    fn line_end(&self, line: usize, line_start: usize) -> usize {
+        let end = match self.line_start(line + 1) {
+            //	Skip the line feed and a carriage return before it.
+            Some(next) => {
+                let end = next - 1;
+                if end > line_start && self.byte_at(end - 1) == Some(b'\r') {
+                    end - 1
+                } else {
+                    end
+                }
+            }
+            None => self.text_len(),
+        };
+        end.max(line_start)
+    }
+
+    //	Number of line feeds before an absolute position.
+    fn line_feeds_before(&self, offset: usize) -> usize {
+        let mut x = self.root;
+        let mut offset = offset;
+        let mut lf = 0;
+        while x != SENTINEL {
+            let node = &self.nodes[x];
+            if offset < node.size_left {
+                x = node.left;
+            } else if offset <= node.size_left + node.piece.length {
+                let pos = self.position_in_buffer(x, offset - node.size_left);
+                return lf + node.line_feeds_left + pos.line - node.piece.start.line;
+            } else {
+                offset -= node.size_left + node.piece.length;
+                lf += node.line_feeds_left + node.piece.line_feed_cnt;
+                x = node.right;
+            }
+        }
+        lf
+    }
+
+    //	Length from the start of a node's piece to the start of the
+    //	line following its `index`th line feed.
+    fn accumulated_value(&self, node: Link, index: usize) -> usize {
+        let piece = self.nodes[node].piece;
+        let line_starts = &self.buffers[piece.buffer_index].line_starts;
+        let expected = piece.start.line + index + 1;
+        let start_offset = line_starts.get(piece.start.line) + piece.start.column;
+        if expected > piece.end.line {
+            piece.length
+        } else {
+            line_starts.get(expected) - start_offset
+        }
+    }
+
+    fn offset_in_buffer(&self, buffer_index: usize, cursor: BufferCursor) -> usize {
+        self.buffers[buffer_index].line_starts.get(cursor.line) + cursor.column
+    }
+
+    //	Buffer cursor `remainder` bytes into a node's piece.
+    fn position_in_buffer(&self, node: Link, remainder: usize) -> BufferCursor {
+        let piece = self.nodes[node].piece;
+        let line_starts = &self.buffers[piece.buffer_index].line_starts;
+        let offset = line_starts.get(piece.start.line) + piece.start.column + remainder;
+
+        //	Find the last line in the piece that starts before the offset.
+        let mut low = piece.start.line;
+        let mut high = piece.end.line;
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if line_starts.get(mid) <= offset {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        BufferCursor::new(low, offset - line_starts.get(low))
+    }
+
+    //	Appends text to the change buffer and returns a piece for it.
+    fn append_change(&mut self, txt: &str) -> Piece {
+        let buffer = &mut self.buffers[0];
+        let start_offset = buffer.buffer.len();
+        let start_line = buffer.line_starts.len() - 1;
+        let start = BufferCursor::new(
+            start_line,
+            start_offset - buffer.line_starts.get(start_line),
+        );
+
+        //	Only copies the change buffer if a snapshot still shares it.
+        Arc::make_mut(&mut buffer.buffer).push_str(txt);
+        let line_starts = create_line_starts_fast(txt, false);
+        for i in 1..line_starts.len() {
+            buffer.line_starts.push(start_offset + line_starts.get(i));
+        }
+
+        let end_line = buffer.line_starts.len() - 1;
+        let end = BufferCursor::new(
+            end_line,
+            buffer.buffer.len() - buffer.line_starts.get(end_line),
+        );
+        Piece::new(0, start, end, txt.len(), end_line - start_line)
+    }
+
+    //	Shortens a node's piece to `remainder` bytes and returns a piece
+    //	for the rest.
+    fn shrink_node(&mut self, node: Link, remainder: usize) -> Piece {
+        let piece = self.nodes[node].piece;
+        let mid = self.position_in_buffer(node, remainder);
+        let left_lf = mid.line - piece.start.line;
+        let right = Piece::new(
+            piece.buffer_index,
+            mid,
+            piece.end,
+            piece.length - remainder,
+            piece.end.line - mid.line,
+        );
+
+        let n = &mut self.nodes[node];
+        n.piece.end = mid;
+        n.piece.length = remainder;
+        n.piece.line_feed_cnt = left_lf;
+        let delta = remainder as isize - piece.length as isize;
+        let lf_delta = left_lf as isize - piece.line_feed_cnt as isize;
+        self.update_tree_metadata(node, delta, lf_delta);
+        right
+    }
+
+    //	Splits the node containing `offset` so a node starts there.
+    fn split(&mut self, offset: usize) {
+        if self.root == SENTINEL {
+            return;
+        }
+        let np = self.node_at(offset);
+        let node_len = self.nodes[np.node].piece.length;
+        if np.remainder == 0 || np.remainder == node_len {
+            return;
+        }
+        let right = self.shrink_node(np.node, np.remainder);
+        self.rb_insert_right(np.node, right);
+    }
+
+    //	Node containing an absolute position. A position on a boundary
+    //	resolves to the node on its left.
+    fn node_at(&self, offset: usize) -> NodePosition {
+        let mut x = self.root;
+        let mut offset = offset;
+        let mut node_start_offset = 0;
+        while x != SENTINEL {
+            let node = &self.nodes[x];
+            if node.size_left > offset {
+                x = node.left;
+            } else if node.size_left + node.piece.length >= offset {
+                node_start_offset += node.size_left;
+                return NodePosition {
+                    node: x,
+                    remainder: offset - node.size_left,
+                    node_start_offset,
+                };
+            } else {
+                offset -= node.size_left + node.piece.length;
+                node_start_offset += node.size_left + node.piece.length;
+                x = node.right;
+            }
+        }
+        NodePosition {
+            node: SENTINEL,
+            remainder: 0,
+            node_start_offset,
+        }
+    }
+
+    //	Node whose piece starts at an absolute position.
+    fn node_starting_at(&self, offset: usize) -> Link {
+        let mut x = self.root;
+        let mut offset = offset;
+        while x != SENTINEL {
+            let node = &self.nodes[x];
+            if offset < node.size_left {
+                x = node.left;
+            } else if offset < node.size_left + node.piece.length {
+                return x;
+            } else {
+                offset -= node.size_left + node.piece.length;
+                x = node.right;
+            }
+        }
+        SENTINEL
+    }
+
+    //	Absolute position of the start of a node's piece.
+    fn offset_of_node(&self, node: Link) -> usize {
+        let mut pos = self.nodes[node].size_left;
+        let mut node = node;
+        while node != self.root {
+            let parent = self.nodes[node].parent;
+            if self.nodes[parent].right == node {
+                pos += self.nodes[parent].size_left + self.nodes[parent].piece.length;
+            }
+            node = parent;
+        }
+        pos
+    }
+
+    //	Next node in text order.
+    pub fn next(&self, node: Link) -> Link {
+        let mut node = node;
+        if self.nodes[node].right != SENTINEL {
+            return self.leftest(self.nodes[node].right);
+        }
+        while self.nodes[node].parent != SENTINEL {
+            let parent = self.nodes[node].parent;
+            if self.nodes[parent].left == node {
+                return parent;
+            }
+            node = parent;
+        }
+        SENTINEL
+    }
+
+    //	Previous node in text order.
+    pub fn prev(&self, node: Link) -> Link {
+        let mut node = node;
+        if self.nodes[node].left != SENTINEL {
+            return self.righttest(self.nodes[node].left);
+        }
+        while self.nodes[node].parent != SENTINEL {
+            let parent = self.nodes[node].parent;
+            if self.nodes[parent].right == node {
+                return parent;
+            }
+            node = parent;
+        }
+        SENTINEL
+    }
+
+    //	Unlinks a node and returns its slot to the arena.
+    fn detach(&mut self, node: Link) {
+        let n = &mut self.nodes[node];
+        n.parent = SENTINEL;
+        n.left = SENTINEL;
+        n.right = SENTINEL;
+        self.free.push(node);
+    }
+
+    fn alloc(&mut self, piece: Piece) -> Link {
+        let node = TreeNode::new(piece, NodeColor::Red);
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn leftest(&self, node: Link) -> Link {
+        let mut node = node;
+        while self.nodes[node].left != SENTINEL {
+            node = self.nodes[node].left;
+        }
+        node
+    }
+
+    fn righttest(&self, node: Link) -> Link {
+        let mut node = node;
+        while self.nodes[node].right != SENTINEL {
+            node = self.nodes[node].right;
+        }
+        node
+    }
+
+    fn calculate_size(&self, node: Link) -> usize {
+        let mut size = 0;
+        let mut node = node;
+        while node != SENTINEL {
+            size += self.nodes[node].size_left + self.nodes[node].piece.length;
+            node = self.nodes[node].right;
+        }
+        size
+    }
+
+    fn calculate_lf(&self, node: Link) -> usize {
+        let mut lf = 0;
+        let mut node = node;
+        while node != SENTINEL {
+            lf += self.nodes[node].line_feeds_left + self.nodes[node].piece.line_feed_cnt;
+            node = self.nodes[node].right;
+        }
+        lf
+    }
+
+    fn reset_sentinel(&mut self) {
+        self.nodes[SENTINEL] = TreeNode::default();
+    }
+
+    fn color(&self, node: Link) -> NodeColor {
+        self.nodes[node].color
+    }
+
+    fn set_color(&mut self, node: Link, color: NodeColor) {
+        self.nodes[node].color = color;
+    }
+
+    fn parent(&self, node: Link) -> Link {
+        self.nodes[node].parent
+    }
+
+    fn left_rotate(&mut self, x: Link) {
+        let y = self.nodes[x].right;
+
+        //	Fix size_left
+        let (size, lf) = (
+            self.nodes[x].size_left + self.nodes[x].piece.length,
+            self.nodes[x].line_feeds_left + self.nodes[x].piece.line_feed_cnt,
+        );
+        self.nodes[y].size_left += size;
+        self.nodes[y].line_feeds_left += lf;
+
+        let y_left = self.nodes[y].left;
+        self.nodes[x].right = y_left;
+        if y_left != SENTINEL {
+            self.nodes[y_left].parent = x;
+        }
+        let x_parent = self.parent(x);
+        self.nodes[y].parent = x_parent;
+        if x_parent == SENTINEL {
+            self.root = y;
+        } else if self.nodes[x_parent].left == x {
+            self.nodes[x_parent].left = y;
+        } else {
+            self.nodes[x_parent].right = y;
+        }
+        self.nodes[y].left = x;
+        self.nodes[x].parent = y;
+    }
+
+    fn right_rotate(&mut self, y: Link) {
+        let x = self.nodes[y].left;
+
+        let x_right = self.nodes[x].right;
+        self.nodes[y].left = x_right;
+        if x_right != SENTINEL {
+            self.nodes[x_right].parent = y;
+        }
+        let y_parent = self.parent(y);
+        self.nodes[x].parent = y_parent;
+
+        //	Fix size_left
+        let (size, lf) = (
+            self.nodes[x].size_left + self.nodes[x].piece.length,
+            self.nodes[x].line_feeds_left + self.nodes[x].piece.line_feed_cnt,
+        );
+        self.nodes[y].size_left -= size;
+        self.nodes[y].line_feeds_left -= lf;
+
+        if y_parent == SENTINEL {
+            self.root = x;
+        } else if self.nodes[y_parent].right == y {
+            self.nodes[y_parent].right = x;
+        } else {
+            self.nodes[y_parent].left = x;
+        }
+        self.nodes[x].right = y;
+        self.nodes[y].parent = x;
+    }
+
+    //	Inserts a piece right after `node` in text order.
+    fn rb_insert_right(&mut self, node: Link, piece: Piece) -> Link {
+        let z = self.alloc(piece);
+        if self.root == SENTINEL {
+            self.root = z;
+            self.set_color(z, NodeColor::Black);
+        } else if self.nodes[node].right == SENTINEL {
+            self.nodes[node].right = z;
+            self.nodes[z].parent = node;
+        } else {
+            let next = self.leftest(self.nodes[node].right);
+            self.nodes[next].left = z;
+            self.nodes[z].parent = next;
+        }
+        self.fix_insert(z);
+        z
+    }
+
+    //	Inserts a piece right before `node` in text order.
+    fn rb_insert_left(&mut self, node: Link, piece: Piece) -> Link {
+        let z = self.alloc(piece);
+        if self.root == SENTINEL {
+            self.root = z;
+            self.set_color(z, NodeColor::Black);
+        } else if self.nodes[node].left == SENTINEL {
+            self.nodes[node].left = z;
+            self.nodes[z].parent = node;
+        } else {
+            let prev = self.righttest(self.nodes[node].left);
+            self.nodes[prev].right = z;
+            self.nodes[z].parent = prev;
+        }
+        self.fix_insert(z);
+        z
+    }
+
+    fn rb_delete(&mut self, z: Link) {
+        let (x, y);
+        if self.nodes[z].left == SENTINEL {
+            y = z;
+            x = self.nodes[y].right;
+        } else if self.nodes[z].right == SENTINEL {
+            y = z;
+            x = self.nodes[y].left;
+        } else {
+            y = self.leftest(self.nodes[z].right);
+            x = self.nodes[y].right;
+        }
+
+        if y == self.root {
+            self.root = x;
+            //	If x is the sentinel the tree is now empty.
+            self.set_color(x, NodeColor::Black);
+            self.detach(z);
+            self.reset_sentinel();
+            self.nodes[self.root].parent = SENTINEL;
+            return;
+        }
+
+        let y_was_red = self.color(y) == NodeColor::Red;
+
+        let y_parent = self.parent(y);
+        if self.nodes[y_parent].left == y {
+            self.nodes[y_parent].left = x;
+        } else {
+            self.nodes[y_parent].right = x;
+        }
+
+        if y == z {
+            self.nodes[x].parent = y_parent;
+            self.recompute_tree_metadata(x);
+        } else {
+            self.nodes[x].parent = if y_parent == z { y } else { y_parent };
+
+            //	As we make changes to x's hierarchy, update size_left of
+            //	the subtree first.
+            self.recompute_tree_metadata(x);
+
+            let zn = self.nodes[z];
+            self.nodes[y].left = zn.left;
+            self.nodes[y].right = zn.right;
+            self.nodes[y].parent = zn.parent;
+            self.nodes[y].color = zn.color;
+
+            if z == self.root {
+                self.root = y;
+            } else if self.nodes[zn.parent].left == z {
+                self.nodes[zn.parent].left = y;
+            } else {
+                self.nodes[zn.parent].right = y;
+            }
+
+            let (y_left, y_right) = (self.nodes[y].left, self.nodes[y].right);
+            if y_left != SENTINEL {
+                self.nodes[y_left].parent = y;
+            }
+            if y_right != SENTINEL {
+                self.nodes[y_right].parent = y;
+            }
+
+            //	We replace z with y, so in this subtree the length
+            //	change is z's piece length.
+            self.nodes[y].size_left = zn.size_left;
+            self.nodes[y].line_feeds_left = zn.line_feeds_left;
+            self.recompute_tree_metadata(y);
+        }
+
+        self.detach(z);
+
+        let x_parent = self.parent(x);
+        if self.nodes[x_parent].left == x {
+            let new_size_left = self.calculate_size(x);
+            let new_lf_left = self.calculate_lf(x);
+            let parent = self.nodes[x_parent];
+            if new_size_left != parent.size_left || new_lf_left != parent.line_feeds_left {
+                let delta = new_size_left as isize - parent.size_left as isize;
+                let lf_delta = new_lf_left as isize - parent.line_feeds_left as isize;
+                self.nodes[x_parent].size_left = new_size_left;
+                self.nodes[x_parent].line_feeds_left = new_lf_left;
+                self.update_tree_metadata(x_parent, delta, lf_delta);
+            }
+        }
+
+        self.recompute_tree_metadata(x_parent);
+
+        if y_was_red {
+            self.reset_sentinel();
+            return;
+        }
+
+        //	RB-DELETE-FIXUP
+        let mut x = x;
+        while x != self.root && self.color(x) == NodeColor::Black {
+            let x_parent = self.parent(x);
+            if x == self.nodes[x_parent].left {
+                let mut w = self.nodes[x_parent].right;
+                if self.color(w) == NodeColor::Red {
+                    self.set_color(w, NodeColor::Black);
+                    self.set_color(x_parent, NodeColor::Red);
+                    self.left_rotate(x_parent);
+                    w = self.nodes[self.parent(x)].right;
+                }
+                let (w_left, w_right) = (self.nodes[w].left, self.nodes[w].right);
+                if self.color(w_left) == NodeColor::Black && self.color(w_right) == NodeColor::Black
+                {
+                    self.set_color(w, NodeColor::Red);
+                    x = self.parent(x);
+                } else {
+                    if self.color(w_right) == NodeColor::Black {
+                        self.set_color(w_left, NodeColor::Black);
+                        self.set_color(w, NodeColor::Red);
+                        self.right_rotate(w);
+                        w = self.nodes[self.parent(x)].right;
+                    }
+                    let x_parent = self.parent(x);
+                    self.set_color(w, self.color(x_parent));
+                    self.set_color(x_parent, NodeColor::Black);
+                    let w_right = self.nodes[w].right;
+                    self.set_color(w_right, NodeColor::Black);
+                    self.left_rotate(x_parent);
+                    x = self.root;
+                }
+            } else {
+                let mut w = self.nodes[x_parent].left;
+                if self.color(w) == NodeColor::Red {
+                    self.set_color(w, NodeColor::Black);
+                    self.set_color(x_parent, NodeColor::Red);
+                    self.right_rotate(x_parent);
+                    w = self.nodes[self.parent(x)].left;
+                }
+                let (w_left, w_right) = (self.nodes[w].left, self.nodes[w].right);
+                if self.color(w_left) == NodeColor::Black && self.color(w_right) == NodeColor::Black
+                {
+                    self.set_color(w, NodeColor::Red);
+                    x = self.parent(x);
+                } else {
+                    if self.color(w_left) == NodeColor::Black {
+                        self.set_color(w_right, NodeColor::Black);
+                        self.set_color(w, NodeColor::Red);
+                        self.left_rotate(w);
+                        w = self.nodes[self.parent(x)].left;
+                    }
+                    let x_parent = self.parent(x);
+                    self.set_color(w, self.color(x_parent));
+                    self.set_color(x_parent, NodeColor::Black);
+                    let w_left = self.nodes[w].left;
+                    self.set_color(w_left, NodeColor::Black);
+                    self.right_rotate(x_parent);
+                    x = self.root;
+                }
+            }
+        }
+        self.set_color(x, NodeColor::Black);
+        self.reset_sentinel();
+    }
+
+    fn fix_insert(&mut self, x: Link) {
+        self.recompute_tree_metadata(x);
+
+        let mut x = x;
+        while x != self.root && self.color(self.parent(x)) == NodeColor::Red {
+            let parent = self.parent(x);
+            let grand_parent = self.parent(parent);
+            if parent == self.nodes[grand_parent].left {
+                let y = self.nodes[grand_parent].right;
+                if self.color(y) == NodeColor::Red {
+                    self.set_color(parent, NodeColor::Black);
+                    self.set_color(y, NodeColor::Black);
+                    self.set_color(grand_parent, NodeColor::Red);
+                    x = grand_parent;
+                } else {
+                    if x == self.nodes[parent].right {
+                        x = parent;
+                        self.left_rotate(x);
+                    }
+                    let parent = self.parent(x);
+                    let grand_parent = self.parent(parent);
+                    self.set_color(parent, NodeColor::Black);
+                    self.set_color(grand_parent, NodeColor::Red);
+                    self.right_rotate(grand_parent);
+                }
+            } else {
+                let y = self.nodes[grand_parent].left;
+                if self.color(y) == NodeColor::Red {
+                    self.set_color(parent, NodeColor::Black);
+                    self.set_color(y, NodeColor::Black);
+                    self.set_color(grand_parent, NodeColor::Red);
+                    x = grand_parent;
+                } else {
+                    if x == self.nodes[parent].left {
+                        x = parent;
+                        self.right_rotate(x);
+                    }
+                    let parent = self.parent(x);
+                    let grand_parent = self.parent(parent);
+                    self.set_color(parent, NodeColor::Black);
+                    self.set_color(grand_parent, NodeColor::Red);
+                    self.left_rotate(grand_parent);
+                }
+            }
+        }
+        let root = self.root;
+        self.set_color(root, NodeColor::Black);
+    }
+
+    //	Propagates a change in a node's length or line feeds to the
+    //	ancestors that have it in their left subtree.
+    fn update_tree_metadata(&mut self, x: Link, delta: isize, lf_delta: isize) {
+        let mut x = x;
+        while x != self.root && x != SENTINEL {
+            let parent = self.parent(x);
+            if self.nodes[parent].left == x {
+                let p = &mut self.nodes[parent];
+                p.size_left = p.size_left.wrapping_add_signed(delta);
+                p.line_feeds_left = p.line_feeds_left.wrapping_add_signed(lf_delta);
+            }
+            x = parent;
+        }
+    }
+
+    fn recompute_tree_metadata(&mut self, x: Link) {
+        if x == self.root {
+            return;
+        }
+
+        //	Go upwards till the node whose left subtree is changed.
+        let mut x = x;
+        while x != self.root && x == self.nodes[self.parent(x)].right {
+            x = self.parent(x);
+        }
+        if x == self.root {
+            //	A node was added to the end of the text.
+            return;
+        }
+
+        //	x is the node whose right subtree is changed.
+        x = self.parent(x);
+        let left = self.nodes[x].left;
+        let delta = self.calculate_size(left) as isize - self.nodes[x].size_left as isize;
+        let lf_delta = self.calculate_lf(left) as isize - self.nodes[x].line_feeds_left as isize;
+        let n = &mut self.nodes[x];
+        n.size_left = n.size_left.wrapping_add_signed(delta);
+        n.line_feeds_left = n.line_feeds_left.wrapping_add_signed(lf_delta);
+
+        //	Go upwards till root. O(log n)
+        while x != self.root && (delta != 0 || lf_delta != 0) {
+            let parent = self.parent(x);
+            if self.nodes[parent].left == x {
+                let p = &mut self.nodes[parent];
+                p.size_left = p.size_left.wrapping_add_signed(delta);
+                p.line_feeds_left = p.line_feeds_left.wrapping_add_signed(lf_delta);
+            }
+            x = parent;
+        }
+    }
+}
+
+//	Immutable version of a piece tree. Reads one piece at a time.
+pub struct PieceTreeSnapshot {
+    buffers: Vec<Arc<String>>,
+    //	Buffer index, start offset and length of each piece.
+    pieces: Vec<(usize, usize, usize)>,
+    index: usize,
 }
 
-const SENTINEL: TreeNode = TreeNode{ piece: None, parent: None, left: None, right: None, color: NodeColor::Black, size_left: 0, line_feeds_left: 0 };
\ No newline at end of file
+impl TextSnapshot for PieceTreeSnapshot {
+    fn read(&mut self) -> Option<Box<str>> {
+        let &(buffer_index, start, length) = self.pieces.get(self.index)?;
+        self.index += 1;
+        Some(self.buffers[buffer_index][start..start + length].into())
+    }
+}
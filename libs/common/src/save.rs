@@ -0,0 +1,101 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::Result;
+
+//	How a buffer is written to disk.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct SaveOptions {
+    //	Copy the previous file to `<name>~` before replacing it.
+    pub backup: bool,
+}
+
+impl SaveOptions {
+    pub fn with_backup(backup: bool) -> Self {
+        Self { backup }
+    }
+}
+
+//	Writes a file by streaming into a temporary file next to it and
+//	renaming that over the target once it is synced, so readers see
+//	either the old or the new content. The permissions of an existing
+//	target are kept and a symlinked target is written through the link.
+pub fn write_atomic<F>(path: &Path, options: &SaveOptions, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let target = match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(err) => return Err(err.into()),
+    };
+    let permissions = match fs::metadata(&target) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    let (tmp_path, file) = create_temp_file(&target)?;
+    let result = (|| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        if let Some(permissions) = permissions.clone() {
+            file.set_permissions(permissions)?;
+        }
+        file.sync_all()?;
+
+        if options.backup && permissions.is_some() {
+            fs::copy(&target, backup_path(&target))?;
+        }
+        fs::rename(&tmp_path, &target)?;
+        sync_parent(&target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+//	Path of the backup kept for a file.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push("~");
+    PathBuf::from(name)
+}
+
+//	Creates a new file in the directory of `target` that no other file
+//	uses.
+fn create_temp_file(target: &Path) -> Result<(PathBuf, File)> {
+    let name = target.file_name().unwrap_or_default();
+    for n in 0.. {
+        let mut tmp_name = OsString::from(".");
+        tmp_name.push(name);
+        tmp_name.push(format!(".{}.{}.tmp", process::id(), n));
+        let tmp_path = target.with_file_name(tmp_name);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+        {
+            Ok(file) => return Ok((tmp_path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    unreachable!()
+}
+
+//	Makes the rename durable. Directories can only be synced on unix.
+fn sync_parent(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
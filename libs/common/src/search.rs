@@ -0,0 +1,236 @@
+use std::{collections::VecDeque, ops::Range};
+
+use regex::Regex;
+
+use crate::textbuffer::Position;
+
+//	How a literal search compares text.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct FindOptions {
+    //	Compare characters exactly instead of by their lowercase form.
+    pub match_case: bool,
+    //	Only match text that is not preceded or followed by a word
+    //	character.
+    pub whole_word: bool,
+}
+
+//	Text found by a search.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Match {
+    pub range: Range<usize>,
+    pub start: Position,
+    pub end: Position,
+}
+
+//	Result of replacing every match of a regex.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Replaced {
+    pub count: usize,
+    //	Ranges of the replacements in the new text.
+    pub ranges: Vec<Range<usize>>,
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+//	Start of a matched character, kept for the last needle length units.
+#[derive(Clone, Copy)]
+struct Unit {
+    offset: usize,
+    //	Whether this is the first unit of a character. A lowercase form
+    //	may have more than one.
+    first: bool,
+    prev: Option<char>,
+    pos: Position,
+}
+
+//	Finds a needle in a chunked text without copying the text. Matches
+//	do not overlap and are passed to `on_match` until it returns false.
+//	`offset` and `pos` are where the chunks start in the text and `prev`
+//	is the character before them.
+pub fn find_in_chunks<'a, I, F>(
+    chunks: I,
+    offset: usize,
+    pos: Position,
+    prev: Option<char>,
+    needle: &str,
+    options: &FindOptions,
+    mut on_match: F,
+) where
+    I: IntoIterator<Item = &'a str>,
+    F: FnMut(Match) -> bool,
+{
+    let needle: Vec<char> = fold(needle, options).collect();
+    if needle.is_empty() {
+        return;
+    }
+    let fail = failure_table(&needle);
+
+    let mut state = 0;
+    let mut window: VecDeque<Unit> = VecDeque::with_capacity(needle.len());
+    //	Whole word matches wait for the character after them.
+    let mut pending: Option<Match> = None;
+    let (mut line, mut line_start) = (pos.line, offset - pos.column);
+    let mut prev = prev;
+    let mut start = offset;
+
+    for chunk in chunks {
+        for c in chunk.chars() {
+            let end = start + c.len_utf8();
+            if let Some(m) = pending.take() {
+                if !is_word_char(c) {
+                    if !on_match(m) {
+                        return;
+                    }
+                    state = 0;
+                }
+            }
+
+            let char_pos = Position::new(line, start - line_start);
+            if c == '\n' {
+                line += 1;
+                line_start = end;
+            }
+            let end_pos = Position::new(line, end - line_start);
+
+            let units = fold_char(c, options);
+            let count = units.clone().count();
+            for (k, u) in units.enumerate() {
+                if window.len() == needle.len() {
+                    window.pop_front();
+                }
+                window.push_back(Unit {
+                    offset: start,
+                    first: k == 0,
+                    prev,
+                    pos: char_pos,
+                });
+
+                while state > 0 && needle[state] != u {
+                    state = fail[state - 1];
+                }
+                if needle[state] == u {
+                    state += 1;
+                }
+                if state < needle.len() {
+                    continue;
+                }
+                state = fail[state - 1];
+
+                //	The match has to start and end on whole characters.
+                let first = window[0];
+                if !first.first || k + 1 != count {
+                    continue;
+                }
+                if options.whole_word && first.prev.is_some_and(is_word_char) {
+                    continue;
+                }
+                let m = Match {
+                    range: first.offset..end,
+                    start: first.pos,
+                    end: end_pos,
+                };
+                if options.whole_word {
+                    pending = Some(m);
+                } else {
+                    if !on_match(m) {
+                        return;
+                    }
+                    state = 0;
+                }
+            }
+            prev = Some(c);
+            start = end;
+        }
+    }
+    if let Some(m) = pending {
+        on_match(m);
+    }
+}
+
+//	Matches of a regex in a text that start at or after `from`. The
+//	whole text is the haystack so anchors and look-around see the text
+//	before `from`.
+pub fn find_regex<F>(text: &str, from: usize, regex: &Regex, mut on_match: F)
+where
+    F: FnMut(Match) -> bool,
+{
+    let mut lines = LineCursor::default();
+    let mut start = from;
+    let mut last_end = None;
+    while let Some(m) = regex.find_at(text, start) {
+        //	Like `Regex::find_iter`, an empty match right after the
+        //	previous match is skipped.
+        if !m.is_empty() || last_end != Some(m.end()) {
+            let found = Match {
+                range: m.range(),
+                start: lines.position(text, m.start()),
+                end: lines.position(text, m.end()),
+            };
+            if !on_match(found) {
+                return;
+            }
+            last_end = Some(m.end());
+            if !m.is_empty() {
+                start = m.end();
+                continue;
+            }
+        }
+        //	Step over the empty match so it is not found again.
+        match text[m.end()..].chars().next() {
+            Some(c) => start = m.end() + c.len_utf8(),
+            None => return,
+        }
+    }
+}
+
+//	Turns increasing offsets into positions by counting the line feeds
+//	between them.
+#[derive(Default)]
+struct LineCursor {
+    offset: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl LineCursor {
+    fn position(&mut self, text: &str, offset: usize) -> Position {
+        for (i, b) in text.as_bytes()[self.offset..offset].iter().enumerate() {
+            if *b == b'\n' {
+                self.line += 1;
+                self.line_start = self.offset + i + 1;
+            }
+        }
+        self.offset = offset;
+        Position::new(self.line, offset - self.line_start)
+    }
+}
+
+fn fold<'a>(txt: &'a str, options: &'a FindOptions) -> impl Iterator<Item = char> + 'a {
+    txt.chars().flat_map(|c| fold_char(c, options))
+}
+
+//	Units a character is compared by.
+fn fold_char(c: char, options: &FindOptions) -> impl Iterator<Item = char> + Clone {
+    let lower = (!options.match_case).then(|| c.to_lowercase());
+    let exact = options.match_case.then_some(c);
+    lower.into_iter().flatten().chain(exact)
+}
+
+//	Length of the longest proper prefix of the needle that is also a
+//	suffix of each of its prefixes.
+fn failure_table(needle: &[char]) -> Vec<usize> {
+    let mut fail = vec![0; needle.len()];
+    let mut k = 0;
+    for i in 1..needle.len() {
+        while k > 0 && needle[i] != needle[k] {
+            k = fail[k - 1];
+        }
+        if needle[i] == needle[k] {
+            k += 1;
+        }
+        fail[i] = k;
+    }
+    fail
+}
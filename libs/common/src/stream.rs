@@ -0,0 +1,142 @@
+use std::{
+    io::{self, BufRead, Read, Write},
+    str::{self, Utf8Error},
+};
+
+use crate::piecetable::{Chunks, PieceTable};
+
+//	Reads the text of a piece table as UTF-8 bytes, one piece at a time,
+//	without copying it first.
+pub struct Reader<'a> {
+    chunks: Chunks<'a>,
+    //	Part of the current piece that was not read yet.
+    chunk: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(chunks: Chunks<'a>) -> Self {
+        Self { chunks, chunk: &[] }
+    }
+}
+
+impl Read for Reader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let chunk = self.fill_buf()?;
+        let n = chunk.len().min(buf.len());
+        buf[..n].copy_from_slice(&chunk[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Reader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.chunk.is_empty() {
+            match self.chunks.next() {
+                Some(chunk) => self.chunk = chunk.as_bytes(),
+                None => break,
+            }
+        }
+        Ok(self.chunk)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.chunk = &self.chunk[amt.min(self.chunk.len())..];
+    }
+}
+
+//	Inserts written UTF-8 bytes into a piece table at a cursor that moves
+//	past the written text. A character may be split between two writes.
+pub struct Writer<'a> {
+    table: &'a mut PieceTable,
+    pos: usize,
+    //	Start of a character whose remaining bytes were not written yet.
+    pending: Vec<u8>,
+}
+
+impl<'a> Writer<'a> {
+    pub(crate) fn new(table: &'a mut PieceTable, pos: usize) -> Self {
+        Self {
+            table,
+            pos,
+            pending: Vec::new(),
+        }
+    }
+
+    //	Position the next write inserts at.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn insert(&mut self, txt: &str) -> io::Result<()> {
+        self.table.try_insert(txt, self.pos)?;
+        self.pos += txt.len();
+        Ok(())
+    }
+
+    //	Completes the pending character with the first bytes.
+    fn write_pending(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let take = buf.len().min(4 - self.pending.len());
+        let mut bytes = self.pending.clone();
+        bytes.extend_from_slice(&buf[..take]);
+        let txt = match str::from_utf8(&bytes) {
+            Ok(txt) => txt,
+            Err(err) if err.valid_up_to() > 0 => valid_prefix(&bytes, &err),
+            Err(err) if err.error_len().is_none() => {
+                self.pending = bytes;
+                return Ok(take);
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        self.insert(txt)?;
+        let written = txt.len() - self.pending.len();
+        self.pending.clear();
+        Ok(written)
+    }
+}
+
+impl Write for Writer<'_> {
+    //	Fails with `InvalidData` on bytes that are not UTF-8 and with
+    //	`InvalidInput` if the cursor is not a valid position. A write may
+    //	stop before invalid bytes, like any partial write.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.pending.is_empty() {
+            return self.write_pending(buf);
+        }
+        match str::from_utf8(buf) {
+            Ok(txt) => {
+                self.insert(txt)?;
+                Ok(buf.len())
+            }
+            Err(err) if err.valid_up_to() > 0 => {
+                self.insert(valid_prefix(buf, &err))?;
+                Ok(err.valid_up_to())
+            }
+            //	Keep the start of a character until the rest is written.
+            Err(err) if err.error_len().is_none() => {
+                self.pending.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+    }
+
+    //	Complete characters are inserted as they are written. Fails with
+    //	`InvalidData` while the start of a character still waits for its
+    //	remaining bytes, which are lost if the writer is dropped.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "incomplete UTF-8 character",
+        ))
+    }
+}
+
+//	Part of the bytes before the invalid or incomplete sequence.
+fn valid_prefix<'a>(bytes: &'a [u8], err: &Utf8Error) -> &'a str {
+    //	SAFETY: `Utf8Error::valid_up_to` bytes are valid UTF-8.
+    unsafe { str::from_utf8_unchecked(&bytes[..err.valid_up_to()]) }
+}
@@ -71,9 +71,291 @@ mod piece_table_tests {
     }
 }
 
+#[cfg(test)]
+mod history_tests {
+    use crate::piecetable::PieceTable;
+
+    fn test_text(pt: &PieceTable, test_str: &str) {
+        let text = pt.get_text(None, None);
+        assert_eq!(test_str, text);
+    }
+
+    #[test]
+    fn undo_insert() {
+        let mut pt = PieceTable::new("Hello World!");
+        pt.insert(" Brave New", 5);
+        assert!(pt.undo());
+        test_text(&pt, "Hello World!");
+        assert_eq!(pt.text_len(), 12);
+        assert_eq!(pt.len(), 1);
+        assert!(!pt.undo());
+    }
+
+    #[test]
+    fn redo_insert() {
+        let mut pt = PieceTable::new("Hello World!");
+        pt.insert(" Brave New", 5);
+        pt.undo();
+        assert!(pt.redo());
+        test_text(&pt, "Hello Brave New World!");
+        assert_eq!(pt.text_len(), 22);
+        assert!(!pt.redo());
+    }
+
+    #[test]
+    fn undo_delete() {
+        let mut pt = PieceTable::new("Hello World!");
+        pt.delete(5, 6);
+        test_text(&pt, "Hello!");
+        pt.undo();
+        test_text(&pt, "Hello World!");
+        assert_eq!(pt.text_len(), 12);
+    }
+
+    #[test]
+    fn typing_run_is_one_step() {
+        let mut pt = PieceTable::new("");
+        for (i, c) in ["a", "b", "c"].iter().enumerate() {
+            pt.insert(c, i);
+        }
+        pt.insert("x", 0);
+        test_text(&pt, "xabc");
+        pt.undo();
+        test_text(&pt, "abc");
+        pt.undo();
+        test_text(&pt, "");
+    }
+
+    #[test]
+    fn backspace_run_is_one_step() {
+        let mut pt = PieceTable::new("Hello World!");
+        pt.delete(11, 1);
+        pt.delete(10, 1);
+        pt.delete(9, 1);
+        test_text(&pt, "Hello Wor");
+        pt.undo();
+        test_text(&pt, "Hello World!");
+        assert!(!pt.can_undo());
+    }
+
+    #[test]
+    fn break_undo_group() {
+        let mut pt = PieceTable::new("");
+        pt.insert("a", 0);
+        pt.break_undo_group();
+        pt.insert("b", 1);
+        pt.undo();
+        test_text(&pt, "a");
+    }
+
+    #[test]
+    fn edit_clears_redo() {
+        let mut pt = PieceTable::new("Hello");
+        pt.insert("!", 5);
+        pt.undo();
+        pt.insert("?", 5);
+        assert!(!pt.can_redo());
+        test_text(&pt, "Hello?");
+    }
+
+    #[test]
+    fn history_depth() {
+        let mut pt = PieceTable::new("");
+        pt.set_history_depth(2);
+        for i in 0..4 {
+            pt.insert("a", 0);
+            pt.break_undo_group();
+            assert_eq!(pt.text_len(), i + 1);
+        }
+        assert!(pt.undo());
+        assert!(pt.undo());
+        assert!(!pt.undo());
+        test_text(&pt, "aa");
+    }
+
+    #[test]
+    fn history_depth_keeps_next_redo() {
+        let mut pt = PieceTable::new("");
+        for c in ["a", "b", "c"] {
+            pt.insert(c, pt.text_len());
+            pt.break_undo_group();
+        }
+        while pt.undo() {}
+        pt.set_history_depth(1);
+        assert!(pt.redo());
+        test_text(&pt, "a");
+        assert!(!pt.redo());
+    }
+}
+
+#[cfg(test)]
+mod line_index_tests {
+    use crate::piecetable::{PieceTable, Position};
+
+    fn new_test_table() -> PieceTable {
+        let mut pt = PieceTable::new("one\ntwo\r\n");
+        pt.insert("three\nfour", 10);
+        pt
+    }
+
+    #[test]
+    fn get_text_range() {
+        let pt = new_test_table();
+        assert_eq!(pt.get_text(Some(7), Some(12)), "\r\nthr");
+        assert_eq!(pt.get_text(Some(9), None), "three\nfour");
+    }
+
+    #[test]
+    fn line_count() {
+        let pt = new_test_table();
+        assert_eq!(pt.line_count(), 4);
+        assert_eq!(PieceTable::new("").line_count(), 1);
+        assert_eq!(PieceTable::new("a\n").line_count(), 2);
+    }
+
+    #[test]
+    fn get_line_content() {
+        let pt = new_test_table();
+        assert_eq!(pt.get_line_content(0).as_deref(), Some("one"));
+        assert_eq!(pt.get_line_content(1).as_deref(), Some("two"));
+        assert_eq!(pt.get_line_content(2).as_deref(), Some("three"));
+        assert_eq!(pt.get_line_content(3).as_deref(), Some("four"));
+        assert_eq!(pt.get_line_content(4), None);
+    }
+
+    #[test]
+    fn offset_at() {
+        let pt = new_test_table();
+        assert_eq!(pt.offset_at(0, 0), Some(0));
+        assert_eq!(pt.offset_at(1, 2), Some(6));
+        assert_eq!(pt.offset_at(2, 0), Some(9));
+        assert_eq!(pt.offset_at(3, 4), Some(19));
+        //	Columns past the end of the line are clamped.
+        assert_eq!(pt.offset_at(1, 10), Some(7));
+        assert_eq!(pt.offset_at(4, 0), None);
+    }
+
+    #[test]
+    fn position_at() {
+        let pt = new_test_table();
+        assert_eq!(pt.position_at(0), Some(Position::new(0, 0)));
+        assert_eq!(pt.position_at(6), Some(Position::new(1, 2)));
+        assert_eq!(pt.position_at(9), Some(Position::new(2, 0)));
+        assert_eq!(pt.position_at(19), Some(Position::new(3, 4)));
+        assert_eq!(pt.position_at(20), None);
+    }
+
+    #[test]
+    fn lines_follow_edits() {
+        let mut pt = new_test_table();
+        pt.delete(3, 1);
+        assert_eq!(pt.line_count(), 3);
+        assert_eq!(pt.get_line_content(0).as_deref(), Some("onetwo"));
+        pt.insert("\n", 1);
+        assert_eq!(pt.get_line_content(0).as_deref(), Some("o"));
+        assert_eq!(pt.get_line_content(1).as_deref(), Some("netwo"));
+        assert_eq!(pt.position_at(pt.text_len()), Some(Position::new(3, 4)));
+        pt.undo();
+        pt.undo();
+        assert_eq!(pt.line_count(), 4);
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use crate::{piecetable::PieceTable, Error};
+
+    #[test]
+    fn from_file_missing() {
+        let res = PieceTable::from_file("does/not/exist.txt");
+        assert!(matches!(res, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn insert_out_of_bounds() {
+        let mut pt = PieceTable::new("Hello");
+        let res = pt.try_insert("!", 6);
+        assert!(matches!(res, Err(Error::OutOfBounds { pos: 6, len: 5 })));
+        assert_eq!(pt.get_text(None, None), "Hello");
+    }
+
+    #[test]
+    fn insert_inside_char() {
+        let mut pt = PieceTable::new("h\u{e9}llo");
+        let res = pt.try_insert("!", 2);
+        assert!(matches!(res, Err(Error::InvalidBoundary(2))));
+        assert!(pt.try_insert("!", 3).is_ok());
+        assert_eq!(pt.get_text(None, None), "h\u{e9}!llo");
+    }
+
+    #[test]
+    fn insert_newline_inside_char() {
+        let mut pt = PieceTable::new("h\u{e9}llo");
+        let res = pt.try_insert_newline(2);
+        assert!(matches!(res, Err(Error::InvalidBoundary(2))));
+        assert!(matches!(
+            pt.try_insert_newline(7),
+            Err(Error::OutOfBounds { pos: 7, len: 6 })
+        ));
+        assert!(pt.try_insert_newline(3).is_ok());
+        assert_eq!(pt.get_text(None, None), "h\u{e9}\nllo");
+    }
+
+    #[test]
+    fn delete_errors() {
+        let mut pt = PieceTable::new("\u{1f600}ab");
+        assert!(matches!(
+            pt.try_delete(0, 2),
+            Err(Error::InvalidBoundary(2))
+        ));
+        assert!(matches!(
+            pt.try_delete(4, 3),
+            Err(Error::OutOfBounds { pos: 7, len: 6 })
+        ));
+        assert_eq!(pt.try_delete(0, 4).unwrap(), 4);
+        assert_eq!(pt.get_text(None, None), "ab");
+    }
+
+    #[test]
+    fn get_text_errors() {
+        let pt = PieceTable::new("\u{e9}t\u{e9}");
+        assert!(matches!(
+            pt.try_get_text(Some(1), None),
+            Err(Error::InvalidBoundary(1))
+        ));
+        assert!(pt.try_get_text(None, Some(9)).is_err());
+        assert_eq!(pt.try_get_text(Some(2), Some(3)).unwrap(), "t");
+    }
+
+    #[test]
+    fn trim_piece_errors() {
+        let mut pt = PieceTable::new("Hello");
+        assert!(matches!(
+            pt.try_trim_piece(1, 0, 1),
+            Err(Error::OutOfBounds { pos: 1, len: 1 })
+        ));
+        assert!(pt.try_trim_piece(0, 6, 1).is_err());
+        assert_eq!(pt.try_trim_piece(0, 3, 10).unwrap(), 2);
+        assert_eq!(pt.get_text(None, None), "Hel");
+    }
+
+    #[test]
+    fn delete_across_pieces() {
+        let mut pt = PieceTable::new("Hello");
+        pt.insert(" ", 5);
+        pt.insert("World!", 6);
+        pt.delete(2, 7);
+        assert_eq!(pt.get_text(None, None), "Held!");
+        pt.delete(0, 5);
+        pt.insert("Cats", 0);
+        assert_eq!(pt.get_text(None, None), "Cats");
+    }
+}
+
 #[cfg(test)]
 mod piece_tree_test {
-    /*
+    use crate::piecetree::PieceTree;
+
     fn new_test_table() -> PieceTree {
         PieceTree::new("Hello World!")
     }
@@ -85,7 +367,7 @@ mod piece_tree_test {
         pt
     }
 
-    fn test_text(pt: &PieceTable, test_str: &str) {
+    fn test_text(pt: &PieceTree, test_str: &str) {
         let text = pt.get_text(None, None);
         assert_eq!(test_str, text);
     }
@@ -141,5 +423,1531 @@ mod piece_tree_test {
         pt.delete(0, 11);
         test_text(&pt, "!");
     }
-    */
+
+    #[test]
+    fn lines() {
+        let mut pt = PieceTree::new("one\ntwo\r\n");
+        pt.insert("three\nfour", 10);
+        assert_eq!(pt.line_count(), 4);
+        assert_eq!(pt.get_line_content(1).as_deref(), Some("two"));
+        assert_eq!(pt.get_line_content(3).as_deref(), Some("four"));
+        assert_eq!(pt.offset_at(2, 3), Some(12));
+        assert_eq!(pt.position_at(12).map(|p| (p.line, p.column)), Some((2, 3)));
+        pt.delete(3, 1);
+        assert_eq!(pt.get_line_content(0).as_deref(), Some("onetwo"));
+    }
+
+    #[test]
+    fn delete_all() {
+        let mut pt = new_test_table_large();
+        pt.delete(0, 12);
+        assert!(pt.is_empty());
+        pt.insert("Cats", 0);
+        test_text(&pt, "Cats");
+    }
+}
+
+#[cfg(test)]
+mod line_starts_tests {
+    use crate::piecetree::{create_line_starts, create_line_starts_fast, UintArray};
+
+    fn to_vec(arr: &UintArray) -> Vec<usize> {
+        (0..arr.len()).map(|i| arr.get(i)).collect()
+    }
+
+    #[test]
+    fn counts() {
+        let ls = create_line_starts(Vec::new(), "a\r\nb\nc\rd\n");
+        assert_eq!(to_vec(&ls.line_starts), vec![0, 3, 5, 9]);
+        assert_eq!((ls.cr, ls.lf, ls.crlf), (1, 2, 1));
+        assert!(ls.is_basic_ascii);
+    }
+
+    #[test]
+    fn basic_ascii() {
+        assert!(create_line_starts(Vec::new(), "a\tb").is_basic_ascii);
+        assert!(!create_line_starts(Vec::new(), "caf\u{e9}").is_basic_ascii);
+        assert!(!create_line_starts(Vec::new(), "bell\u{7}").is_basic_ascii);
+    }
+
+    #[test]
+    fn reuses_storage() {
+        let ls = create_line_starts(vec![7, 8, 9], "a\nb");
+        assert_eq!(to_vec(&ls.line_starts), vec![0, 2]);
+    }
+
+    #[test]
+    fn narrowest_array() {
+        let small = create_line_starts_fast("a\nb", true);
+        assert!(matches!(small, UintArray::Uint16Array(_)));
+
+        let large = "a".repeat(70000) + "\nb";
+        let ls = create_line_starts_fast(&large, true);
+        assert!(matches!(ls, UintArray::Uint32Array(_)));
+        assert_eq!(to_vec(&ls), vec![0, 70001]);
+
+        let growable = create_line_starts_fast("a\nb", false);
+        assert!(matches!(growable, UintArray::UintArray(_)));
+    }
+}
+
+#[cfg(test)]
+mod text_buffer_tests {
+    use crate::{piecetable::PieceTable, piecetree::PieceTree, Error, Position, TextBuffer};
+
+    //	Runs the same edits against every backend.
+    fn backends(txt: &str) -> Vec<Box<dyn TextBuffer>> {
+        vec![
+            Box::new(PieceTable::new(txt)),
+            Box::new(PieceTree::new(txt)),
+        ]
+    }
+
+    #[test]
+    fn edits() {
+        for mut buf in backends("Hello World!") {
+            buf.insert(" Brave New", 5);
+            buf.delete(0, 6);
+            assert_eq!(buf.get_text(None, None), "Brave New World!");
+            assert_eq!(buf.get_text(Some(6), Some(9)), "New");
+            assert_eq!(buf.text_len(), 16);
+        }
+    }
+
+    #[test]
+    fn lines() {
+        for mut buf in backends("one\ntwo") {
+            buf.insert("\r\nthree", 7);
+            assert_eq!(buf.line_count(), 3);
+            assert_eq!(buf.get_line_content(1).as_deref(), Some("two"));
+            assert_eq!(buf.offset_at(2, 1), Some(10));
+            assert_eq!(buf.position_at(10), Some(Position::new(2, 1)));
+        }
+    }
+
+    #[test]
+    fn errors() {
+        for mut buf in backends("\u{e9}") {
+            assert!(matches!(
+                buf.try_insert("a", 1),
+                Err(Error::InvalidBoundary(1))
+            ));
+            assert!(matches!(
+                buf.try_delete(0, 3),
+                Err(Error::OutOfBounds { .. })
+            ));
+            assert!(buf.try_get_text(Some(1), None).is_err());
+            assert!(buf.is_char_boundary(2));
+        }
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use std::thread;
+
+    use crate::{piecetable::PieceTable, piecetree::PieceTree, TextBuffer, TextSnapshot};
+
+    fn backends(txt: &str) -> Vec<Box<dyn TextBuffer>> {
+        vec![
+            Box::new(PieceTable::new(txt)),
+            Box::new(PieceTree::new(txt)),
+        ]
+    }
+
+    #[test]
+    fn read_chunks() {
+        let mut pt = PieceTable::new("Hello World!");
+        pt.insert(" Brave New", 5);
+        let mut snapshot = pt.create_snapshot();
+        assert_eq!(snapshot.read().as_deref(), Some("Hello"));
+        assert_eq!(snapshot.read().as_deref(), Some(" Brave New"));
+        assert_eq!(snapshot.read().as_deref(), Some(" World!"));
+        assert_eq!(snapshot.read(), None);
+    }
+
+    #[test]
+    fn unaffected_by_edits() {
+        for mut buf in backends("Hello") {
+            buf.insert(" World", 5);
+            let mut snapshot = buf.create_snapshot();
+            buf.insert("!", 11);
+            buf.delete(0, 6);
+            assert_eq!(snapshot.read_to_string(), "Hello World");
+            assert_eq!(buf.get_text(None, None), "World!");
+        }
+    }
+
+    #[test]
+    fn read_on_thread() {
+        let mut pt = PieceTree::new("Hello");
+        pt.insert(" World", 5);
+        let mut snapshot = pt.create_snapshot();
+        let handle = thread::spawn(move || snapshot.read_to_string());
+        pt.insert("!", 11);
+        assert_eq!(handle.join().unwrap(), "Hello World");
+    }
+}
+
+#[cfg(test)]
+mod chunks_tests {
+    use crate::{piecetable::PieceTable, piecetree::PieceTree, Error};
+
+    //	The space is typed last so it does not continue the piece of
+    //	"World!".
+    fn new_test_table() -> PieceTable {
+        let mut pt = PieceTable::new("Hello");
+        pt.insert("World!", 5);
+        pt.insert(" ", 5);
+        pt
+    }
+
+    #[test]
+    fn one_chunk_per_piece() {
+        let pt = new_test_table();
+        let chunks: Vec<&str> = pt.chunks(None, None).collect();
+        assert_eq!(chunks, vec!["Hello", " ", "World!"]);
+    }
+
+    #[test]
+    fn clipped_to_range() {
+        let pt = new_test_table();
+        let chunks: Vec<&str> = pt.chunks(Some(3), Some(8)).collect();
+        assert_eq!(chunks, vec!["lo", " ", "Wo"]);
+        let chunks: Vec<&str> = pt.chunks(Some(6), None).collect();
+        assert_eq!(chunks, vec!["World!"]);
+        assert_eq!(pt.chunks(Some(5), Some(5)).count(), 0);
+    }
+
+    #[test]
+    fn reversed_range_is_empty() {
+        let pt = new_test_table();
+        assert_eq!(pt.chunks(Some(5), Some(3)).count(), 0);
+        assert_eq!(pt.chunks(Some(3), Some(3)).count(), 0);
+        assert_eq!(pt.get_text(Some(5), Some(3)), "");
+        assert!(matches!(
+            pt.try_get_text(Some(5), Some(3)),
+            Err(Error::InvalidRange { start: 5, end: 3 })
+        ));
+
+        let tree = PieceTree::new("Hello World!");
+        assert_eq!(tree.get_text(Some(5), Some(3)), "");
+        assert!(matches!(
+            tree.try_get_text(Some(5), Some(3)),
+            Err(Error::InvalidRange { start: 5, end: 3 })
+        ));
+    }
+
+    #[test]
+    fn skips_empty_pieces() {
+        let mut pt = PieceTable::new("");
+        pt.insert("abc", 0);
+        let chunks: Vec<&str> = pt.chunks(None, None).collect();
+        assert_eq!(chunks, vec!["abc"]);
+    }
+}
+
+#[cfg(test)]
+mod unicode_tests {
+    use crate::{piecetable::PieceTable, unicode::OffsetUnit, Error, Position};
+
+    //	"e" with a combining acute accent is one grapheme of two chars.
+    const TEXT: &str = "a\u{1f600}e\u{301}\u{4e16}";
+
+    fn new_test_table() -> PieceTable {
+        let mut pt = PieceTable::new("a\u{1f600}e");
+        pt.insert("\u{301}\u{4e16}", 6);
+        pt
+    }
+
+    #[test]
+    fn lengths() {
+        let pt = new_test_table();
+        assert_eq!(pt.get_text(None, None), TEXT);
+        assert_eq!(pt.len_in(OffsetUnit::Byte), 11);
+        assert_eq!(pt.len_in(OffsetUnit::Char), 5);
+        assert_eq!(pt.len_in(OffsetUnit::Utf16), 6);
+        assert_eq!(pt.len_in(OffsetUnit::Grapheme), 4);
+    }
+
+    #[test]
+    fn to_byte_offset() {
+        let pt = new_test_table();
+        assert_eq!(pt.to_byte_offset(2, OffsetUnit::Char).unwrap(), 5);
+        assert_eq!(pt.to_byte_offset(3, OffsetUnit::Utf16).unwrap(), 5);
+        assert_eq!(pt.to_byte_offset(3, OffsetUnit::Grapheme).unwrap(), 8);
+        assert!(matches!(
+            pt.to_byte_offset(2, OffsetUnit::Utf16),
+            Err(Error::InvalidBoundary(2))
+        ));
+        assert!(matches!(
+            pt.to_byte_offset(5, OffsetUnit::Grapheme),
+            Err(Error::OutOfBounds { pos: 5, len: 4 })
+        ));
+    }
+
+    #[test]
+    fn from_byte_offset() {
+        let pt = new_test_table();
+        assert_eq!(pt.from_byte_offset(8, OffsetUnit::Char).unwrap(), 4);
+        assert_eq!(pt.from_byte_offset(8, OffsetUnit::Utf16).unwrap(), 5);
+        assert_eq!(pt.from_byte_offset(8, OffsetUnit::Grapheme).unwrap(), 3);
+        assert!(matches!(
+            pt.from_byte_offset(6, OffsetUnit::Grapheme),
+            Err(Error::InvalidBoundary(6))
+        ));
+        assert!(pt.from_byte_offset(2, OffsetUnit::Char).is_err());
+    }
+
+    #[test]
+    fn edits_in_units() {
+        let mut pt = new_test_table();
+        pt.insert_in("!", 3, OffsetUnit::Grapheme).unwrap();
+        assert_eq!(pt.get_text(None, None), "a\u{1f600}e\u{301}!\u{4e16}");
+        assert_eq!(pt.delete_in(1, 2, OffsetUnit::Utf16).unwrap(), 4);
+        assert_eq!(pt.get_text(None, None), "ae\u{301}!\u{4e16}");
+        assert_eq!(
+            pt.get_text_in(Some(1), Some(3), OffsetUnit::Grapheme)
+                .unwrap(),
+            "e\u{301}!"
+        );
+    }
+
+    #[test]
+    fn lines_in_units() {
+        let mut pt = PieceTable::new("x\n");
+        pt.insert("\u{1f600}y", 2);
+        assert_eq!(pt.offset_at_in(1, 2, OffsetUnit::Utf16).unwrap(), 6);
+        assert_eq!(pt.offset_at_in(1, 9, OffsetUnit::Char).unwrap(), 7);
+        assert_eq!(
+            pt.position_at_in(6, OffsetUnit::Utf16).unwrap(),
+            Position::new(1, 2)
+        );
+        assert_eq!(
+            pt.position_at_in(6, OffsetUnit::Char).unwrap(),
+            Position::new(1, 1)
+        );
+    }
+}
+
+#[cfg(test)]
+mod apply_edits_tests {
+    use crate::{piecetable::PieceTable, Edit, Error};
+
+    fn new_test_table() -> PieceTable {
+        let mut pt = PieceTable::new("Hello");
+        pt.insert(" ", 5);
+        pt.insert("World!", 6);
+        pt
+    }
+
+    #[test]
+    fn applies_all_edits() {
+        let mut pt = new_test_table();
+        let edits = [
+            Edit::new(6..11, "Cats"),
+            Edit::insert(0, ">> "),
+            Edit::delete(4..6),
+        ];
+        pt.apply_edits(&edits).unwrap();
+        assert_eq!(pt.get_text(None, None), ">> HellCats!");
+        assert_eq!(pt.text_len(), 12);
+    }
+
+    #[test]
+    fn inverse_edits_restore_text() {
+        let mut pt = new_test_table();
+        let edits = [Edit::new(0..5, "Goodbye"), Edit::new(11..12, "?\n")];
+        let inverse = pt.apply_edits(&edits).unwrap();
+        assert_eq!(pt.get_text(None, None), "Goodbye World?\n");
+        assert_eq!(inverse[0], Edit::new(0..7, "Hello"));
+        assert_eq!(inverse[1], Edit::new(13..15, "!"));
+        pt.apply_edits(&inverse).unwrap();
+        assert_eq!(pt.get_text(None, None), "Hello World!");
+        assert_eq!(pt.line_count(), 1);
+    }
+
+    #[test]
+    fn inverse_edits_restore_unsorted_adjacent_edits() {
+        let mut pt = PieceTable::new("abcdef");
+        let edits = [Edit::delete(3..5), Edit::delete(1..3)];
+        let inverse = pt.apply_edits(&edits).unwrap();
+        assert_eq!(pt.get_text(None, None), "af");
+        pt.apply_edits(&inverse).unwrap();
+        assert_eq!(pt.get_text(None, None), "abcdef");
+
+        let edits = [
+            Edit::new(4..4, "2"),
+            Edit::new(2..4, "1"),
+            Edit::insert(2, "0"),
+        ];
+        let inverse = pt.apply_edits(&edits).unwrap();
+        assert_eq!(pt.get_text(None, None), "ab012ef");
+        pt.apply_edits(&inverse).unwrap();
+        assert_eq!(pt.get_text(None, None), "abcdef");
+    }
+
+    #[test]
+    fn one_undo_step() {
+        let mut pt = new_test_table();
+        pt.apply_edits(&[Edit::insert(0, "a"), Edit::insert(12, "b")])
+            .unwrap();
+        assert_eq!(pt.get_text(None, None), "aHello World!b");
+        pt.undo();
+        assert_eq!(pt.get_text(None, None), "Hello World!");
+    }
+
+    #[test]
+    fn rejects_overlapping_ranges() {
+        let mut pt = new_test_table();
+        let res = pt.apply_edits(&[Edit::delete(0..5), Edit::new(3..7, "x")]);
+        assert!(matches!(
+            res,
+            Err(Error::OverlappingRanges { start: 3, end: 5 })
+        ));
+        assert_eq!(pt.get_text(None, None), "Hello World!");
+        //	The failed batch did not add an undo step.
+        pt.undo();
+        assert_eq!(pt.get_text(None, None), "Hello");
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        let mut pt = PieceTable::new("h\u{e9}");
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = Edit::delete(2..1);
+        assert!(matches!(
+            pt.apply_edits(&[reversed]),
+            Err(Error::InvalidRange { start: 2, end: 1 })
+        ));
+        assert!(matches!(
+            pt.apply_edits(&[Edit::insert(0, "a"), Edit::delete(2..3)]),
+            Err(Error::InvalidBoundary(2))
+        ));
+        assert!(pt.apply_edits(&[Edit::insert(4, "a")]).is_err());
+        assert_eq!(pt.get_text(None, None), "h\u{e9}");
+    }
+}
+
+#[cfg(test)]
+mod events_tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{piecetable::PieceTable, Change, ChangeEvent, ChangeReason, Edit};
+
+    fn subscribed_table(txt: &str) -> (PieceTable, Arc<Mutex<Vec<ChangeEvent>>>) {
+        let mut pt = PieceTable::new(txt);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        pt.subscribe(move |e| sink.lock().unwrap().push(e.clone()));
+        (pt, events)
+    }
+
+    #[test]
+    fn insert_and_delete_events() {
+        let (mut pt, events) = subscribed_table("Hello\nWorld");
+        pt.insert("a\nb\n", 5);
+        pt.delete(0, 9);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].version, 1);
+        assert_eq!(events[0].reason, ChangeReason::Edit);
+        assert_eq!(events[0].changes, vec![Change::new(5..5, "a\nb\n", 0)]);
+        assert_eq!(events[0].changes[0].line_delta(), 2);
+
+        assert_eq!(events[1].version, 2);
+        let change = &events[1].changes[0];
+        assert_eq!(change.range, 0..9);
+        assert_eq!(change.removed_len(), 9);
+        assert_eq!(change.removed_line_feeds, 2);
+        assert_eq!(change.line_delta(), -2);
+    }
+
+    #[test]
+    fn batch_edits_are_one_event() {
+        let (mut pt, events) = subscribed_table("one\ntwo\nthree");
+        pt.apply_edits(&[Edit::new(0..4, "1"), Edit::insert(8, "2\n")])
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].changes,
+            vec![Change::new(8..8, "2\n", 0), Change::new(0..4, "1", 1)]
+        );
+    }
+
+    #[test]
+    fn undo_and_redo_events() {
+        let (mut pt, events) = subscribed_table("Hello World");
+        pt.delete(5, 6);
+        pt.undo();
+        pt.redo();
+
+        let events = events.lock().unwrap();
+        let reasons: Vec<ChangeReason> = events.iter().map(|e| e.reason).collect();
+        assert_eq!(
+            reasons,
+            [ChangeReason::Edit, ChangeReason::Undo, ChangeReason::Redo]
+        );
+        assert_eq!(events[1].changes, vec![Change::new(5..5, " World", 0)]);
+        assert_eq!(events[2].changes[0].range, 5..11);
+        assert_eq!(events[2].changes[0].text, "");
+        assert_eq!(pt.version(), 3);
+    }
+
+    #[test]
+    fn no_event_without_change() {
+        let (mut pt, events) = subscribed_table("Hello");
+        pt.insert("", 2);
+        pt.delete(2, 0);
+        assert!(!pt.undo());
+        assert!(events.lock().unwrap().is_empty());
+        assert_eq!(pt.version(), 0);
+    }
+
+    #[test]
+    fn unsubscribe_stops_events() {
+        let (mut pt, events) = subscribed_table("Hello");
+        let count = Arc::new(Mutex::new(0));
+        let counter = count.clone();
+        let id = pt.subscribe(move |_| *counter.lock().unwrap() += 1);
+        pt.insert("!", 5);
+        assert!(pt.unsubscribe(id));
+        assert!(!pt.unsubscribe(id));
+        pt.insert("!", 6);
+
+        assert_eq!(*count.lock().unwrap(), 1);
+        assert_eq!(events.lock().unwrap().len(), 2);
+    }
+}
+
+#[cfg(test)]
+mod marker_tests {
+    use crate::{piecetable::PieceTable, Edit, Stickiness};
+
+    #[test]
+    fn markers_follow_inserts() {
+        let mut pt = PieceTable::new("Hello World");
+        let left = pt.add_marker(5, Stickiness::Left);
+        let right = pt.add_marker(5, Stickiness::Right);
+        let end = pt.add_marker(11, Stickiness::Left);
+        pt.insert(",", 5);
+        pt.insert(">> ", 0);
+
+        assert_eq!(pt.marker_offset(left), Some(8));
+        assert_eq!(pt.marker_offset(right), Some(9));
+        assert_eq!(pt.marker_offset(end), Some(15));
+    }
+
+    #[test]
+    fn deleted_markers_are_reported() {
+        let mut pt = PieceTable::new("Hello World");
+        let start = pt.add_marker(2, Stickiness::Right);
+        let inside = pt.add_marker(4, Stickiness::Left);
+        let end = pt.add_marker(7, Stickiness::Left);
+        pt.delete(2, 5);
+
+        assert_eq!(pt.marker_offset(start), Some(2));
+        assert_eq!(pt.marker_offset(inside), None);
+        assert_eq!(pt.marker_offset(end), Some(2));
+        assert_eq!(pt.take_deleted_markers(), vec![inside]);
+        assert!(pt.take_deleted_markers().is_empty());
+        assert_eq!(pt.marker_count(), 2);
+    }
+
+    #[test]
+    fn markers_follow_batch_edits_and_undo() {
+        let mut pt = PieceTable::new("one two three");
+        let two = pt.add_marker(4, Stickiness::Left);
+        let three = pt.add_marker(8, Stickiness::Left);
+        pt.apply_edits(&[Edit::new(0..3, "1"), Edit::new(4..7, "2")])
+            .unwrap();
+        assert_eq!(pt.get_text(None, None), "1 2 three");
+        assert_eq!(pt.marker_offset(two), Some(2));
+        assert_eq!(pt.marker_offset(three), Some(4));
+
+        pt.undo();
+        assert_eq!(pt.marker_offset(three), Some(8));
+    }
+
+    #[test]
+    fn markers_in_range() {
+        let mut pt = PieceTable::new("abcdefghij");
+        let ids: Vec<_> = (0..10)
+            .map(|i| pt.add_marker(i, Stickiness::Left))
+            .collect();
+        pt.insert("xx", 0);
+        assert!(pt.remove_marker(ids[5]));
+        assert!(!pt.remove_marker(ids[5]));
+
+        let found = pt.markers_in(5..9);
+        assert_eq!(found, vec![(ids[3], 5), (ids[4], 6), (ids[6], 8)]);
+        assert_eq!(
+            pt.add_marker(100, Stickiness::Left),
+            pt.markers_in(12..13)[0].0
+        );
+    }
+}
+
+#[cfg(test)]
+mod decoration_tests {
+    use crate::{piecetable::PieceTable, RangeStickiness};
+
+    #[test]
+    fn decorations_follow_edits() {
+        let mut pt = PieceTable::new("Hello World");
+        let never = pt.add_decoration(6..11, "highlight", RangeStickiness::NEVER_GROWS);
+        let always = pt.add_decoration(6..11, "selection", RangeStickiness::ALWAYS_GROWS);
+        pt.insert(">", 6);
+        pt.insert("<", 12);
+        pt.insert("!!", 0);
+
+        assert_eq!(pt.get_text(None, None), "!!Hello >World<");
+        assert_eq!(pt.decoration(never).unwrap().range, 9..14);
+        assert_eq!(pt.decoration(always).unwrap().range, 8..15);
+        assert_eq!(pt.decoration(always).unwrap().class, "selection");
+    }
+
+    #[test]
+    fn deleted_text_shrinks_decorations() {
+        let mut pt = PieceTable::new("one two three");
+        let start_cut = pt.add_decoration(2..7, "a", RangeStickiness::default());
+        let end_cut = pt.add_decoration(5..11, "b", RangeStickiness::default());
+        let inside = pt.add_decoration(4..5, "c", RangeStickiness::default());
+        pt.delete(3, 3);
+
+        assert_eq!(pt.get_text(None, None), "oneo three");
+        assert_eq!(pt.decoration(start_cut).unwrap().range, 2..4);
+        assert_eq!(pt.decoration(end_cut).unwrap().range, 3..8);
+        assert_eq!(pt.decoration(inside).unwrap().range, 3..3);
+
+        pt.undo();
+        assert_eq!(pt.decoration(end_cut).unwrap().range, 6..11);
+    }
+
+    #[test]
+    fn query_by_offset_and_line() {
+        let mut pt = PieceTable::new("first\nsecond\nthird\n");
+        let a = pt.add_decoration(1..3, "a", RangeStickiness::default());
+        let b = pt.add_decoration(4..8, "b", RangeStickiness::default());
+        let c = pt.add_decoration(14..16, "c", RangeStickiness::default());
+        let ids = |d: Vec<crate::Decoration>| d.into_iter().map(|d| d.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(pt.decorations_in(0..2)), vec![a]);
+        assert_eq!(ids(pt.decorations_in(3..14)), vec![a, b, c]);
+        assert_eq!(ids(pt.decorations_in_lines(0..1)), vec![a, b]);
+        assert_eq!(ids(pt.decorations_in_lines(1..2)), vec![b]);
+        assert_eq!(ids(pt.decorations_in_lines(2..4)), vec![c]);
+        assert!(pt.decorations_in_lines(4..5).is_empty());
+
+        assert!(pt.remove_decoration(b));
+        assert_eq!(ids(pt.decorations_in_lines(0..2)), vec![a]);
+        assert_eq!(pt.decoration_count(), 2);
+    }
+}
+
+#[cfg(test)]
+mod eol_tests {
+    use crate::{
+        piecetable::PieceTable,
+        piecetree::{create_line_starts, AVERAGE_BUFFER_SIZE},
+        Eol, EolStats,
+    };
+
+    #[test]
+    fn counts_line_endings() {
+        let stats = EolStats::of("a\r\nb\nc\rd\r\n");
+        assert_eq!(
+            stats,
+            EolStats {
+                lf: 1,
+                crlf: 2,
+                cr: 1
+            }
+        );
+        assert_eq!(stats.dominant(), Some(Eol::CrLf));
+        assert!(stats.is_mixed());
+        assert_eq!(EolStats::of_chunks(["a\r", "\nb\r"]).crlf, 1);
+        assert_eq!(EolStats::of("abc").dominant(), None);
+
+        let line_starts = create_line_starts(Vec::new(), "a\r\nb\nc\rd\r\n");
+        assert_eq!(EolStats::from(&line_starts), stats);
+    }
+
+    #[test]
+    fn stats_from_chunk_line_starts() {
+        let chunks = ["a\r", "\nb\r", "", "\nc\r", "d\n"];
+        let line_starts: Vec<_> = chunks
+            .iter()
+            .map(|chunk| create_line_starts(Vec::new(), chunk))
+            .collect();
+        let stats = EolStats::of_line_starts(chunks.into_iter().zip(&line_starts));
+        assert_eq!(stats, EolStats::of_chunks(chunks));
+        assert_eq!((stats.crlf, stats.lf, stats.cr), (2, 1, 1));
+
+        //	The first CRLF is split between the chunks the text is loaded
+        //	in. Counted as a CR and an LF, LF would win.
+        let txt = format!("{}\r\nb\nc\r\n", "a".repeat(AVERAGE_BUFFER_SIZE - 1));
+        let pt = PieceTable::new(&txt);
+        assert_eq!(pt.buffer_count(), 3);
+        assert_eq!(pt.eol(), Eol::CrLf);
+        assert_eq!(pt.line_count(), 4);
+        let pt = PieceTable::from_reader(txt.as_bytes()).unwrap();
+        assert_eq!(pt.eol(), Eol::CrLf);
+        assert_eq!(pt.line_count(), 4);
+    }
+
+    #[test]
+    fn detects_eol_on_load() {
+        let mut pt = PieceTable::new("one\r\ntwo\r\nthree\n");
+        assert_eq!(pt.eol(), Eol::CrLf);
+        assert!(pt.has_mixed_eol());
+        pt.insert_newline(3);
+        assert_eq!(pt.get_text(None, None), "one\r\n\r\ntwo\r\nthree\n");
+
+        assert_eq!(PieceTable::new("plain").eol(), Eol::Lf);
+    }
+
+    #[test]
+    fn set_eol_converts_text() {
+        let mut pt = PieceTable::new("a\nb\r\nc\rd\n");
+        pt.set_eol(Eol::CrLf);
+        assert_eq!(pt.get_text(None, None), "a\r\nb\r\nc\rd\r\n");
+        assert_eq!(pt.eol(), Eol::CrLf);
+        assert_eq!(pt.line_count(), 4);
+
+        pt.set_eol(Eol::Lf);
+        assert_eq!(pt.get_text(None, None), "a\nb\nc\rd\n");
+        assert_eq!(pt.eol_stats().crlf, 0);
+        pt.insert_newline(0);
+        assert_eq!(pt.get_text(None, None), "\na\nb\nc\rd\n");
+
+        pt.undo();
+        pt.undo();
+        assert_eq!(pt.get_text(None, None), "a\r\nb\r\nc\rd\r\n");
+    }
+}
+
+#[cfg(test)]
+mod encoding_tests {
+    use crate::{
+        encoding::{self, Encoding},
+        piecetable::PieceTable,
+        Error,
+    };
+
+    fn round_trip(bytes: &[u8]) -> Vec<u8> {
+        let pt = PieceTable::from_bytes(bytes);
+        let mut out = Vec::new();
+        pt.write_to(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn detects_encodings() {
+        assert_eq!(encoding::detect(b"\xEF\xBB\xBFabc"), (Encoding::Utf8, true));
+        assert_eq!(encoding::detect(b"\xFF\xFEa\0"), (Encoding::Utf16Le, true));
+        assert_eq!(encoding::detect(b"\xFE\xFF\0a"), (Encoding::Utf16Be, true));
+        assert_eq!(encoding::detect(b"a\0b\0"), (Encoding::Utf16Le, false));
+        assert_eq!(encoding::detect(b"\0a\0b"), (Encoding::Utf16Be, false));
+        assert_eq!(
+            encoding::detect("caf\u{e9}".as_bytes()),
+            (Encoding::Utf8, false)
+        );
+        assert_eq!(encoding::detect(b"caf\xE9"), (Encoding::Latin1, false));
+        assert_eq!(
+            encoding::detect(b"\x93quoted\x94"),
+            (Encoding::Windows1252, false)
+        );
+    }
+
+    #[test]
+    fn decodes_text() {
+        let pt = PieceTable::from_bytes(b"\xFF\xFEh\0i\0\n\0");
+        assert_eq!(pt.get_text(None, None), "hi\n");
+        assert_eq!(pt.encoding(), Encoding::Utf16Le);
+        assert!(pt.has_bom());
+
+        let pt = PieceTable::from_bytes(b"\x93caf\xE9\x94");
+        assert_eq!(pt.get_text(None, None), "\u{201c}caf\u{e9}\u{201d}");
+    }
+
+    #[test]
+    fn writes_original_bytes() {
+        let files: [&[u8]; 6] = [
+            b"\xEF\xBB\xBFplain\r\n",
+            b"\xFF\xFEa\0=\xD8\x00\xDE",
+            b"\xFE\xFF\0a\xD8=\xDE\0",
+            b"a\0b\0",
+            b"caf\xE9 \xFF",
+            b"\x80 \x81 \x9F",
+        ];
+        for bytes in files {
+            assert_eq!(round_trip(bytes), bytes);
+        }
+    }
+
+    #[test]
+    fn malformed_input_is_not_written() {
+        //	UTF-8 after a byte order mark, and a lone UTF-16 surrogate.
+        let files: [(&[u8], &str); 2] = [
+            (b"\xEF\xBB\xBFhi\xFF", "hi\u{FFFD}"),
+            (b"\xFF\xFEh\0\x00\xD8i\0", "h\u{FFFD}i"),
+        ];
+        for (bytes, txt) in files {
+            for pt in [
+                PieceTable::from_bytes(bytes),
+                PieceTable::from_reader(bytes).unwrap(),
+            ] {
+                assert_eq!(pt.get_text(None, None), txt);
+                assert!(pt.is_lossy());
+                let mut out = Vec::new();
+                assert!(matches!(pt.write_to(&mut out), Err(Error::Lossy)));
+                assert!(out.is_empty());
+            }
+        }
+        assert!(!PieceTable::from_bytes(b"\xFF\xFEh\0i\0").is_lossy());
+        assert!(!PieceTable::from_bytes(b"caf\xE9").is_lossy());
+    }
+
+    #[test]
+    fn edits_are_encoded() {
+        let mut pt = PieceTable::from_bytes(b"caf\xE9");
+        pt.insert(" cr\u{e8}me", 5);
+        let mut out = Vec::new();
+        pt.write_to(&mut out).unwrap();
+        assert_eq!(out, b"caf\xE9 cr\xE8me");
+
+        pt.insert("\u{20ac}", 0);
+        let res = pt.write_to(&mut Vec::new());
+        assert!(matches!(
+            res,
+            Err(Error::Unencodable {
+                pos: 0,
+                encoding: "ISO-8859-1"
+            })
+        ));
+
+        pt.set_encoding(Encoding::Windows1252, true);
+        assert!(!pt.has_bom());
+        out.clear();
+        pt.write_to(&mut out).unwrap();
+        assert_eq!(out, b"\x80caf\xE9 cr\xE8me");
+    }
+}
+
+#[cfg(test)]
+mod save_tests {
+    use std::{fs, path::PathBuf, process};
+
+    use crate::{piecetable::PieceTable, save::backup_path, SaveOptions};
+
+    //	Empty directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("trext-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn saves_and_tracks_dirty_state() {
+        let dir = test_dir("save");
+        let path = dir.join("file.txt");
+        let mut pt = PieceTable::new("Hello");
+        assert!(!pt.is_dirty());
+
+        pt.insert(" World", 5);
+        assert!(pt.is_dirty());
+        pt.save_to(&path, &SaveOptions::default()).unwrap();
+        assert!(!pt.is_dirty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Hello World");
+
+        pt.delete(0, 6);
+        assert!(pt.is_dirty());
+        pt.undo();
+        assert!(!pt.is_dirty());
+
+        //	Only the saved file is left in the directory.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_backup_and_encoding() {
+        let dir = test_dir("backup");
+        let path = dir.join("latin1.txt");
+        fs::write(&path, b"caf\xE9").unwrap();
+
+        let mut pt = PieceTable::from_file(path.to_str().unwrap()).unwrap();
+        pt.insert("!", 5);
+        pt.save_to(&path, &SaveOptions::with_backup(true)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xE9!");
+        assert_eq!(fs::read(backup_path(&path)).unwrap(), b"caf\xE9");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("permissions");
+        let path = dir.join("script.sh");
+        fs::write(&path, "echo").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+
+        let mut pt = PieceTable::new("echo hi");
+        pt.save_to(&path, &SaveOptions::default()).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_save_leaves_no_temp_file() {
+        let dir = test_dir("unencodable");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"caf\xE9").unwrap();
+
+        let mut pt = PieceTable::from_file(path.to_str().unwrap()).unwrap();
+        pt.insert("\u{20ac}", 0);
+        assert!(pt.save_to(&path, &SaveOptions::default()).is_err());
+        assert!(pt.is_dirty());
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xE9");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod compaction_tests {
+    use crate::{piecetable::PieceTable, CompactionPolicy, Stickiness};
+
+    //	Table with one piece per inserted word.
+    fn fragmented_table() -> PieceTable {
+        let mut pt = PieceTable::new("");
+        for word in ["one ", "two ", "three ", "four ", "five"] {
+            pt.insert(word, pt.text_len());
+            pt.break_undo_group();
+        }
+        pt.insert(">", 0);
+        pt
+    }
+
+    #[test]
+    fn compact_keeps_text_and_undo() {
+        let mut pt = fragmented_table();
+        let text = pt.get_text(None, None);
+        let marker = pt.add_marker(5, Stickiness::Left);
+        assert!(pt.len() > 2);
+
+        pt.compact();
+        assert_eq!(pt.len(), 1);
+        assert_eq!(pt.get_text(None, None), text);
+        assert_eq!(pt.line_count(), 1);
+
+        pt.undo();
+        assert_eq!(pt.get_text(None, None), "one two three four five");
+        pt.undo();
+        assert_eq!(pt.get_text(None, None), "one two three four ");
+        pt.redo();
+        pt.redo();
+        assert_eq!(pt.get_text(None, None), text);
+        //	Undo only reports the text that changed.
+        assert_eq!(pt.marker_offset(marker), Some(5));
+    }
+
+    #[test]
+    fn merges_contiguous_pieces() {
+        let mut pt = PieceTable::new("Hello World");
+        pt.delete(5, 0);
+        pt.trim_piece(0, 11, 0);
+        pt.insert("!", 5);
+        pt.delete(5, 1);
+        assert_eq!(pt.len(), 2);
+
+        pt.compact();
+        assert_eq!(pt.len(), 1);
+        assert!(!pt.is_dirty());
+    }
+
+    #[test]
+    fn policy_by_piece_count() {
+        let mut pt = PieceTable::new("");
+        pt.set_compaction_policy(CompactionPolicy::by_piece_count(8));
+        for i in 0..100 {
+            pt.insert("ab", i % 3);
+        }
+        assert!(pt.len() <= 8);
+        assert_eq!(pt.text_len(), 200);
+        assert_eq!(pt.compaction_policy().max_pieces, Some(8));
+    }
+
+    #[test]
+    fn policy_by_garbage_ratio() {
+        let mut pt = PieceTable::new("Hello");
+        pt.set_compaction_policy(CompactionPolicy::by_garbage_ratio(0.5));
+        pt.insert(" World", 5);
+        pt.delete(5, 6);
+        //	The history still points into the add buffer.
+        assert!(pt.garbage_ratio() > 0.5);
+
+        pt.clear_history();
+        assert_eq!(pt.garbage_ratio(), 0.0);
+        assert_eq!(pt.len(), 1);
+        assert_eq!(pt.get_text(None, None), "Hello");
+        assert!(!pt.is_dirty());
+    }
+}
+
+#[cfg(test)]
+mod typing_tests {
+    use crate::piecetable::PieceTable;
+
+    #[test]
+    fn sequential_typing_extends_piece() {
+        let mut pt = PieceTable::new("Hello");
+        for (i, c) in " typed text".chars().enumerate() {
+            pt.insert(&c.to_string(), 5 + i);
+        }
+        assert_eq!(pt.get_text(None, None), "Hello typed text");
+        assert_eq!(pt.len(), 2);
+
+        pt.undo();
+        assert_eq!(pt.get_text(None, None), "Hello");
+    }
+
+    #[test]
+    fn jumping_starts_new_piece() {
+        let mut pt = PieceTable::new("ac");
+        pt.insert("b", 1);
+        pt.insert("d", 3);
+        //	"d" follows "b" in the add buffer but not in the text.
+        assert_eq!(pt.get_text(None, None), "abcd");
+        assert_eq!(pt.len(), 4);
+
+        pt.insert("e", 4);
+        assert_eq!(pt.len(), 4);
+        pt.insert("x", 2);
+        assert_eq!(pt.get_text(None, None), "abxcde");
+        assert_eq!(pt.len(), 5);
+    }
+}
+
+#[cfg(test)]
+mod search_tests {
+    use crate::{piecetable::PieceTable, FindOptions, Position};
+
+    fn ranges(pt: &PieceTable, needle: &str, options: &FindOptions) -> Vec<(usize, usize)> {
+        pt.find_all(needle, options)
+            .iter()
+            .map(|m| (m.range.start, m.range.end))
+            .collect()
+    }
+
+    #[test]
+    fn find_across_pieces() {
+        let mut pt = PieceTable::new("Hello");
+        pt.insert("World", 5);
+        pt.insert(" ", 5);
+        assert_eq!(pt.len(), 3);
+
+        let m = pt.find("lo W", 0, &FindOptions::default()).unwrap();
+        assert_eq!(m.range, 3..7);
+        assert!(pt.find("lo W", 4, &FindOptions::default()).is_none());
+    }
+
+    #[test]
+    fn find_positions() {
+        let pt = PieceTable::new("one\ntwo two\nthree");
+        let matches = pt.find_all("two", &FindOptions::default());
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[1].range, 8..11);
+        assert_eq!(matches[1].start, Position::new(1, 4));
+        assert_eq!(matches[1].end, Position::new(1, 7));
+
+        let m = pt.find("o\nth", 0, &FindOptions::default()).unwrap();
+        assert_eq!(m.start, Position::new(1, 6));
+        assert_eq!(m.end, Position::new(2, 2));
+    }
+
+    #[test]
+    fn match_case() {
+        let pt = PieceTable::new("Straße STRASSE Ärger ärger");
+        let exact = FindOptions {
+            match_case: true,
+            ..Default::default()
+        };
+        assert_eq!(ranges(&pt, "ärger", &exact), vec![(23, 29)]);
+        assert_eq!(
+            ranges(&pt, "ärger", &FindOptions::default()),
+            vec![(16, 22), (23, 29)]
+        );
+        assert_eq!(
+            ranges(&pt, "strasse", &FindOptions::default()),
+            vec![(8, 15)]
+        );
+    }
+
+    #[test]
+    fn whole_word() {
+        let mut pt = PieceTable::new("cat concat cat_ cat.");
+        pt.insert("s", 20);
+        let options = FindOptions {
+            whole_word: true,
+            ..Default::default()
+        };
+        assert_eq!(ranges(&pt, "cat", &options), vec![(0, 3), (16, 19)]);
+        assert_eq!(ranges(&pt, "cat", &FindOptions::default()).len(), 4);
+        assert!(pt.find("at", 0, &options).is_none());
+    }
+
+    #[test]
+    fn find_all_does_not_overlap() {
+        let pt = PieceTable::new("aaaaa");
+        assert_eq!(
+            ranges(&pt, "aa", &FindOptions::default()),
+            vec![(0, 2), (2, 4)]
+        );
+        assert!(pt.find("", 0, &FindOptions::default()).is_none());
+        assert!(pt.find_all("", &FindOptions::default()).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod regex_tests {
+    use crate::{piecetable::PieceTable, Edit, Position, Regex};
+
+    fn split_table() -> PieceTable {
+        let mut pt = PieceTable::new("let a = 1;\n");
+        pt.insert("let b = 2;\n", 11);
+        pt.insert("let c = 3;", 0);
+        pt.insert("\n", 10);
+        pt
+    }
+
+    #[test]
+    fn find_regex_across_pieces() {
+        let pt = split_table();
+        assert!(pt.len() > 2);
+        let re = Regex::new(r"(?s)c = 3;\nlet a").unwrap();
+        let m = pt.find_regex(&re, 0).unwrap();
+        assert_eq!(m.range, 4..16);
+        assert_eq!(m.start, Position::new(0, 4));
+        assert_eq!(m.end, Position::new(1, 5));
+
+        let re = Regex::new(r"(?m)^let (\w)").unwrap();
+        let matches = pt.find_all_regex(&re);
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[2].start, Position::new(2, 0));
+        assert_eq!(pt.find_regex(&re, 1).unwrap().range, 11..16);
+    }
+
+    #[test]
+    fn find_regex_after_edits() {
+        let mut pt = split_table();
+        let re = Regex::new(r"let (\w) = 4").unwrap();
+        assert!(pt.find_regex(&re, 0).is_none());
+
+        pt.apply_edits(&[Edit::new(8..9, "4")]).unwrap();
+        assert_eq!(pt.find_regex(&re, 0).unwrap().range, 0..9);
+        pt.undo();
+        assert!(pt.find_all_regex(&re).is_empty());
+    }
+
+    #[test]
+    fn replace_all_with_captures() {
+        let mut pt = split_table();
+        let re = Regex::new(r"let (\w) = (\d)").unwrap();
+        let replaced = pt.replace_all(&re, "const ${1}: i32 = $2");
+        assert_eq!(replaced.count, 3);
+        assert_eq!(
+            pt.get_text(None, None),
+            "const c: i32 = 3;\nconst a: i32 = 1;\nconst b: i32 = 2;\n"
+        );
+        assert_eq!(replaced.ranges, vec![0..16, 18..34, 36..52]);
+
+        pt.undo();
+        assert_eq!(
+            pt.get_text(None, None),
+            "let c = 3;\nlet a = 1;\nlet b = 2;\n"
+        );
+        pt.redo();
+        assert!(pt.get_text(None, None).starts_with("const c"));
+    }
+
+    #[test]
+    fn replace_all_without_matches() {
+        let mut pt = PieceTable::new("abc");
+        let replaced = pt.replace_all(&Regex::new("x").unwrap(), "y");
+        assert_eq!(replaced.count, 0);
+        assert!(replaced.ranges.is_empty());
+        assert!(!pt.undo());
+    }
+}
+
+#[cfg(test)]
+mod mapped_tests {
+    use std::{fs, path::PathBuf, process};
+
+    use crate::{
+        piecetable::PieceTable, piecetree::AVERAGE_BUFFER_SIZE, Encoding, Eol, Error, SaveOptions,
+        TextSnapshot,
+    };
+
+    fn test_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("trext-{}-{}", name, process::id()));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn maps_and_edits_file() {
+        //	The detection sample ends inside the euro sign.
+        let txt = format!("{}€\nsecond line\n", "a".repeat(64 * 1024 - 1));
+        let path = test_file("mapped", txt.as_bytes());
+        let mut pt = PieceTable::from_file_mapped(path.to_str().unwrap()).unwrap();
+        assert_eq!(pt.text_len(), txt.len());
+        assert_eq!(pt.line_count(), 3);
+        assert_eq!(pt.get_line_content(1).unwrap(), "second line");
+        assert_eq!(pt.get_text(Some(64 * 1024 - 1), Some(64 * 1024 + 2)), "€");
+        assert!(!pt.is_char_boundary(64 * 1024));
+
+        pt.insert("first ", 0);
+        assert_eq!(pt.get_text(None, Some(8)), "first aa");
+        let mut snapshot = pt.create_snapshot();
+        pt.save_to(&path, &SaveOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("first {txt}"));
+
+        //	The map still reads the replaced file.
+        pt.undo();
+        assert_eq!(pt.get_text(None, None), txt);
+        drop(pt);
+        let mut len = 0;
+        while let Some(chunk) = snapshot.read() {
+            len += chunk.len();
+        }
+        assert_eq!(len, txt.len() + 6);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_bytes_are_not_saved() {
+        //	The Latin-1 byte is past the sample the encoding is detected
+        //	from.
+        let mut bytes = "a".repeat(70_000).into_bytes();
+        bytes.extend_from_slice(b"caf\xE9\n");
+        let path = test_file("mapped-invalid", &bytes);
+        let mut pt = PieceTable::from_file_mapped(path.to_str().unwrap()).unwrap();
+        assert_eq!(pt.get_text(Some(0), Some(3)), "aaa");
+        assert_eq!(pt.get_text(Some(70_000), None), "caf?\n");
+        assert_eq!(pt.text_len(), bytes.len());
+        assert_eq!(pt.line_count(), 2);
+        assert!(pt.is_lossy());
+
+        pt.insert("b", 0);
+        let err = pt.save_to(&path, &SaveOptions::default()).unwrap_err();
+        assert!(matches!(err, Error::Lossy));
+        assert!(pt.write_to(Vec::new()).is_err());
+        assert!(pt.is_dirty());
+        drop(pt);
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        //	Reading the whole file keeps the byte through a save.
+        let mut pt = PieceTable::from_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(pt.encoding(), Encoding::Latin1);
+        assert!(pt.get_text(None, None).ends_with("café\n"));
+        pt.insert("b", 0);
+        pt.save_to(&path, &SaveOptions::default()).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[1..], bytes[..]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lossy_check_reads_unread_chunks() {
+        let mut bytes = "a".repeat(AVERAGE_BUFFER_SIZE * 2).into_bytes();
+        bytes.push(0xFF);
+        let path = test_file("mapped-unread", &bytes);
+        let pt = PieceTable::from_file_mapped(path.to_str().unwrap()).unwrap();
+        assert_eq!(pt.get_text(None, Some(3)), "aaa");
+        assert!(pt.is_lossy());
+
+        fs::write(&path, "a".repeat(AVERAGE_BUFFER_SIZE * 2)).unwrap();
+        let pt = PieceTable::from_file_mapped(path.to_str().unwrap()).unwrap();
+        assert!(!pt.is_lossy());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn detects_bom_and_other_encodings() {
+        let path = test_file("mapped-bom", b"\xEF\xBB\xBFline\r\n");
+        let pt = PieceTable::from_file_mapped(path.to_str().unwrap()).unwrap();
+        assert_eq!(pt.get_text(None, None), "line\r\n");
+        assert!(pt.has_bom());
+        assert_eq!(pt.eol(), Eol::CrLf);
+
+        fs::write(&path, b"\xFF\xFEh\0i\0").unwrap();
+        let pt = PieceTable::from_file_mapped(path.to_str().unwrap()).unwrap();
+        assert_eq!(pt.encoding(), Encoding::Utf16Le);
+        assert_eq!(pt.get_text(None, None), "hi");
+
+        fs::write(&path, b"").unwrap();
+        let pt = PieceTable::from_file_mapped(path.to_str().unwrap()).unwrap();
+        assert_eq!(pt.text_len(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod chunked_buffer_tests {
+    use crate::{piecetable::PieceTable, piecetree::AVERAGE_BUFFER_SIZE};
+
+    #[test]
+    fn loads_text_in_chunks() {
+        //	The first chunk ends inside "€", so it is cut before it.
+        let txt = format!(
+            "{}€ line\n{}",
+            "a".repeat(AVERAGE_BUFFER_SIZE - 1),
+            "b\n".repeat(AVERAGE_BUFFER_SIZE)
+        );
+        let pt = PieceTable::new(&txt);
+        assert_eq!(pt.buffer_count(), 5);
+        assert_eq!(pt.len(), 4);
+        assert_eq!(pt.get_text(None, None), txt);
+        assert_eq!(pt.line_count(), AVERAGE_BUFFER_SIZE + 2);
+        assert_eq!(
+            pt.get_line_content(0).unwrap().len(),
+            AVERAGE_BUFFER_SIZE + 7
+        );
+        assert_eq!(pt.get_line_content(AVERAGE_BUFFER_SIZE).unwrap(), "b");
+    }
+
+    #[test]
+    fn add_buffer_rolls_over() {
+        let mut pt = PieceTable::new("");
+        let line = "x".repeat(AVERAGE_BUFFER_SIZE / 2 + 1);
+        pt.insert(&line, 0);
+        assert_eq!(pt.buffer_count(), 2);
+        pt.insert(&line, 0);
+        assert_eq!(pt.buffer_count(), 3);
+
+        //	Typing continues the piece at the end of the new add buffer.
+        pt.insert("y", line.len());
+        pt.insert("z", line.len() + 1);
+        assert_eq!(pt.buffer_count(), 3);
+        assert_eq!(pt.len(), 3);
+        assert_eq!(
+            pt.get_text(Some(line.len() - 1), Some(line.len() + 3)),
+            "xyzx"
+        );
+
+        while pt.undo() {}
+        assert_eq!(pt.text_len(), 0);
+    }
+}
+
+#[cfg(test)]
+mod builder_tests {
+    use crate::{
+        piecetable::PieceTable, piecetree::AVERAGE_BUFFER_SIZE, Encoding, Eol, PieceTableBuilder,
+    };
+
+    #[test]
+    fn joins_split_characters() {
+        let txt = "héllo\r\nwörld €𝄞\r\n";
+        let mut builder = PieceTableBuilder::new();
+        for b in txt.as_bytes() {
+            builder.accept_chunk(&[*b]);
+        }
+        let pt = builder.finish();
+        assert_eq!(pt.get_text(None, None), txt);
+        assert_eq!(pt.line_count(), 3);
+        assert_eq!(pt.get_line_content(1).unwrap(), "wörld €𝄞");
+        assert_eq!(pt.eol(), Eol::CrLf);
+    }
+
+    #[test]
+    fn reads_large_input_in_chunks() {
+        let txt = "line €\n".repeat(AVERAGE_BUFFER_SIZE / 4);
+        let pt = PieceTable::from_reader(txt.as_bytes()).unwrap();
+        assert_eq!(pt.buffer_count(), 4);
+        assert_eq!(pt.get_text(None, None), txt);
+        assert_eq!(pt.line_count(), AVERAGE_BUFFER_SIZE / 4 + 1);
+        assert_eq!(
+            pt.get_line_content(AVERAGE_BUFFER_SIZE / 8).unwrap(),
+            "line €"
+        );
+    }
+
+    #[test]
+    fn detects_encoding_and_replaces_invalid_bytes() {
+        let pt = PieceTable::from_reader(&b"\xFF\xFEh\0i\0"[..]).unwrap();
+        assert_eq!(pt.encoding(), Encoding::Utf16Le);
+        assert!(pt.has_bom());
+        assert_eq!(pt.get_text(None, None), "hi");
+
+        let pt = PieceTable::from_reader(&b"\xEF\xBB\xBFok\xFF"[..]).unwrap();
+        assert_eq!(pt.encoding(), Encoding::Utf8);
+        assert_eq!(pt.get_text(None, None), "ok\u{FFFD}");
+
+        let pt = PieceTable::from_reader(&b""[..]).unwrap();
+        assert_eq!(pt.text_len(), 0);
+    }
+
+    #[test]
+    fn falls_back_to_single_byte_encodings() {
+        let pt = PieceTable::from_reader(&b"caf\xE9\n"[..]).unwrap();
+        assert_eq!(pt.encoding(), Encoding::Latin1);
+        assert_eq!(pt.get_text(None, None), "café\n");
+        let mut out = Vec::new();
+        pt.write_to(&mut out).unwrap();
+        assert_eq!(out, b"caf\xE9\n");
+
+        //	Text read as UTF-8 before the invalid byte is read again.
+        let mut bytes = "é".repeat(AVERAGE_BUFFER_SIZE).into_bytes();
+        bytes.extend_from_slice(b"\x93quoted\x94 \xC3");
+        let mut builder = PieceTableBuilder::new();
+        for chunk in bytes.chunks(1000) {
+            builder.accept_chunk(chunk);
+        }
+        let pt = builder.finish();
+        let expected = PieceTable::from_bytes(&bytes);
+        assert_eq!(pt.encoding(), Encoding::Windows1252);
+        assert_eq!(pt.encoding(), expected.encoding());
+        assert_eq!(pt.get_text(None, None), expected.get_text(None, None));
+        let mut out = Vec::new();
+        pt.write_to(&mut out).unwrap();
+        assert_eq!(out, bytes);
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use std::io::{self, BufRead, Read, Write};
+
+    use crate::piecetable::PieceTable;
+
+    fn split_table() -> PieceTable {
+        let mut pt = PieceTable::new("one\nthree\n");
+        pt.insert("two\n", 4);
+        pt
+    }
+
+    #[test]
+    fn display_writes_every_piece() {
+        let pt = split_table();
+        assert_eq!(pt.len(), 3);
+        assert_eq!(format!("{pt}"), "one\ntwo\nthree\n");
+        assert_eq!(pt.to_string(), pt.get_text(None, None));
+    }
+
+    #[test]
+    fn reader_reads_across_pieces() {
+        let pt = split_table();
+        let mut out = Vec::new();
+        io::copy(&mut pt.reader(), &mut out).unwrap();
+        assert_eq!(out, b"one\ntwo\nthree\n");
+
+        let lines: Vec<String> = pt.reader().lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, ["one", "two", "three"]);
+
+        let mut reader = pt.reader();
+        let mut buf = [0; 6];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"one\ntw");
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "o\nthree\n");
+    }
+
+    #[test]
+    fn writer_appends_and_inserts() {
+        let mut pt = PieceTable::new("Hello");
+        let name = "World";
+        write!(pt.writer(), ", {name}!").unwrap();
+        assert_eq!(pt.get_text(None, None), "Hello, World!");
+
+        let mut writer = pt.writer_at(5);
+        //	"é" is split between two writes.
+        writer.write_all(b" d\xC3").unwrap();
+        writer.write_all(b"\xA9j\xC3\xA0 vu").unwrap();
+        assert_eq!(writer.position(), 15);
+        assert_eq!(pt.get_text(None, None), "Hello déjà vu, World!");
+
+        let mut writer = pt.writer_at(0);
+        let err = writer.write_all(b"ok\xFF").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(pt.get_text(None, Some(4)), "okHe");
+
+        let err = pt.writer_at(100).write_all(b"x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn writer_flush_fails_inside_character() {
+        let mut pt = PieceTable::new("");
+        let mut writer = pt.writer();
+        writer.write_all(b"caf\xC3").unwrap();
+        let err = writer.flush().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        writer.write_all(b"\xA9").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(pt.get_text(None, None), "café");
+    }
+}
+
+#[cfg(test)]
+mod lines_tests {
+    use std::borrow::Cow;
+
+    use crate::piecetable::PieceTable;
+
+    #[test]
+    fn lines_strip_line_endings() {
+        let pt = PieceTable::new("one\r\ntwo\n\nthree");
+        let lines: Vec<_> = pt.lines(0..usize::MAX).collect();
+        assert_eq!(lines, ["one", "two", "", "three"]);
+        assert!(lines.iter().all(|l| matches!(l, Cow::Borrowed(_))));
+
+        let lines: Vec<_> = pt.lines_rev(0..usize::MAX).collect();
+        assert_eq!(lines, ["three", "", "two", "one"]);
+
+        let pt = PieceTable::new("last\n");
+        assert_eq!(pt.lines(0..9).collect::<Vec<_>>(), ["last", ""]);
+        assert_eq!(pt.lines_rev(0..9).collect::<Vec<_>>(), ["", "last"]);
+    }
+
+    #[test]
+    fn lines_in_range() {
+        let pt = PieceTable::new("a\nb\nc\nd");
+        assert_eq!(pt.lines(1..3).collect::<Vec<_>>(), ["b", "c"]);
+        assert_eq!(pt.lines_rev(1..3).collect::<Vec<_>>(), ["c", "b"]);
+        assert_eq!(pt.lines(2..9).collect::<Vec<_>>(), ["c", "d"]);
+        assert_eq!(pt.lines_rev(2..9).collect::<Vec<_>>(), ["d", "c"]);
+        assert_eq!(pt.lines(4..9).count(), 0);
+        assert_eq!(pt.lines_rev(4..9).count(), 0);
+        assert_eq!(pt.lines(2..2).count(), 0);
+        assert_eq!(pt.lines_rev(2..2).count(), 0);
+    }
+
+    #[test]
+    fn lines_across_pieces_are_owned() {
+        let mut pt = PieceTable::new("first\r\nsecond line\n");
+        pt.insert("ond\r", 10);
+        pt.insert("\nsec", 14);
+        assert_eq!(pt.get_text(None, None), "first\r\nsecond\r\nsecond line\n");
+
+        let lines: Vec<_> = pt.lines(0..usize::MAX).collect();
+        assert_eq!(lines, ["first", "second", "second line", ""]);
+        assert!(matches!(lines[0], Cow::Borrowed(_)));
+        assert!(matches!(lines[1], Cow::Owned(_)));
+        assert!(matches!(lines[2], Cow::Owned(_)));
+
+        let lines: Vec<_> = pt.lines_rev(0..usize::MAX).collect();
+        assert_eq!(lines, ["", "second line", "second", "first"]);
+    }
 }
@@ -0,0 +1,206 @@
+use std::ops::Range;
+
+use crate::{piecetable::PieceTable, piecetree::PieceTree, Result};
+
+//	Zero based line and column in the text.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+//	Replaces a byte range of the text with new text.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn new(range: Range<usize>, text: impl Into<String>) -> Self {
+        Self {
+            range,
+            text: text.into(),
+        }
+    }
+
+    pub fn insert(pos: usize, text: impl Into<String>) -> Self {
+        Self::new(pos..pos, text)
+    }
+
+    pub fn delete(range: Range<usize>) -> Self {
+        Self::new(range, String::new())
+    }
+}
+
+//	Owned, immutable version of a text buffer that can be read on
+//	another thread while the buffer keeps changing.
+pub trait TextSnapshot {
+    //	Next chunk of the content, or None once everything was read.
+    fn read(&mut self) -> Option<Box<str>>;
+
+    //	Reads the remaining chunks into one string.
+    fn read_to_string(&mut self) -> String {
+        let mut ret = String::new();
+        while let Some(chunk) = self.read() {
+            ret.push_str(&chunk);
+        }
+        ret
+    }
+}
+
+//	Operations shared by every text buffer backend. Positions are byte
+//	offsets into the text and lines end after a line feed.
+pub trait TextBuffer {
+    //	Inserts text at a position. Positions past the end append to it.
+    fn insert(&mut self, txt: &str, pos: usize);
+
+    fn try_insert(&mut self, txt: &str, pos: usize) -> Result<()>;
+
+    //	Deletes `length` bytes starting at `start`. The length is clamped
+    //	to the end of the text.
+    fn delete(&mut self, start: usize, length: usize);
+
+    //	Returns the number of bytes removed.
+    fn try_delete(&mut self, start: usize, length: usize) -> Result<usize>;
+
+    //	Text between two absolute positions. Missing positions default
+    //	to the start and end of the text.
+    fn get_text(&self, start: Option<usize>, end: Option<usize>) -> String;
+
+    fn try_get_text(&self, start: Option<usize>, end: Option<usize>) -> Result<String>;
+
+    //	Length of the text in bytes.
+    fn text_len(&self) -> usize;
+
+    fn is_char_boundary(&self, pos: usize) -> bool;
+
+    //	Number of lines in the text. A text without line feeds has one line.
+    fn line_count(&self) -> usize;
+
+    //	Absolute position of a zero based line and column. The column is
+    //	clamped to the end of the line.
+    fn offset_at(&self, line: usize, column: usize) -> Option<usize>;
+
+    //	Zero based line and column of an absolute position.
+    fn position_at(&self, offset: usize) -> Option<Position>;
+
+    //	Content of a zero based line without its line ending.
+    fn get_line_content(&self, line: usize) -> Option<String>;
+
+    fn create_snapshot(&self) -> Box<dyn TextSnapshot + Send>;
+}
+
+impl TextBuffer for PieceTable {
+    fn insert(&mut self, txt: &str, pos: usize) {
+        PieceTable::insert(self, txt, pos)
+    }
+
+    fn try_insert(&mut self, txt: &str, pos: usize) -> Result<()> {
+        PieceTable::try_insert(self, txt, pos)
+    }
+
+    fn delete(&mut self, start: usize, length: usize) {
+        PieceTable::delete(self, start, length)
+    }
+
+    fn try_delete(&mut self, start: usize, length: usize) -> Result<usize> {
+        PieceTable::try_delete(self, start, length)
+    }
+
+    fn get_text(&self, start: Option<usize>, end: Option<usize>) -> String {
+        PieceTable::get_text(self, start, end)
+    }
+
+    fn try_get_text(&self, start: Option<usize>, end: Option<usize>) -> Result<String> {
+        PieceTable::try_get_text(self, start, end)
+    }
+
+    fn text_len(&self) -> usize {
+        PieceTable::text_len(self)
+    }
+
+    fn is_char_boundary(&self, pos: usize) -> bool {
+        PieceTable::is_char_boundary(self, pos)
+    }
+
+    fn line_count(&self) -> usize {
+        PieceTable::line_count(self)
+    }
+
+    fn offset_at(&self, line: usize, column: usize) -> Option<usize> {
+        PieceTable::offset_at(self, line, column)
+    }
+
+    fn position_at(&self, offset: usize) -> Option<Position> {
+        PieceTable::position_at(self, offset)
+    }
+
+    fn get_line_content(&self, line: usize) -> Option<String> {
+        PieceTable::get_line_content(self, line)
+    }
+
+    fn create_snapshot(&self) -> Box<dyn TextSnapshot + Send> {
+        Box::new(PieceTable::create_snapshot(self))
+    }
+}
+
+impl TextBuffer for PieceTree {
+    fn insert(&mut self, txt: &str, pos: usize) {
+        PieceTree::insert(self, txt, pos)
+    }
+
+    fn try_insert(&mut self, txt: &str, pos: usize) -> Result<()> {
+        PieceTree::try_insert(self, txt, pos)
+    }
+
+    fn delete(&mut self, start: usize, length: usize) {
+        PieceTree::delete(self, start, length)
+    }
+
+    fn try_delete(&mut self, start: usize, length: usize) -> Result<usize> {
+        PieceTree::try_delete(self, start, length)
+    }
+
+    fn get_text(&self, start: Option<usize>, end: Option<usize>) -> String {
+        PieceTree::get_text(self, start, end)
+    }
+
+    fn try_get_text(&self, start: Option<usize>, end: Option<usize>) -> Result<String> {
+        PieceTree::try_get_text(self, start, end)
+    }
+
+    fn text_len(&self) -> usize {
+        PieceTree::text_len(self)
+    }
+
+    fn is_char_boundary(&self, pos: usize) -> bool {
+        PieceTree::is_char_boundary(self, pos)
+    }
+
+    fn line_count(&self) -> usize {
+        PieceTree::line_count(self)
+    }
+
+    fn offset_at(&self, line: usize, column: usize) -> Option<usize> {
+        PieceTree::offset_at(self, line, column)
+    }
+
+    fn position_at(&self, offset: usize) -> Option<Position> {
+        PieceTree::position_at(self, offset)
+    }
+
+    fn get_line_content(&self, line: usize) -> Option<String> {
+        PieceTree::get_line_content(self, line)
+    }
+
+    fn create_snapshot(&self) -> Box<dyn TextSnapshot + Send> {
+        Box::new(PieceTree::create_snapshot(self))
+    }
+}
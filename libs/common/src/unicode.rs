@@ -0,0 +1,139 @@
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+use crate::{Error, Result};
+
+//	Units a text offset can be counted in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OffsetUnit {
+    //	UTF-8 bytes, the unit buffers use internally.
+    Byte,
+    //	Unicode scalar values.
+    Char,
+    //	UTF-16 code units, used by language servers.
+    Utf16,
+    //	Extended grapheme clusters, what a user sees as one character.
+    Grapheme,
+}
+
+//	Byte offset after `count` units of the chunks.
+pub fn byte_offset<'a, I>(chunks: I, count: usize, unit: OffsetUnit) -> Result<usize>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut bytes = 0;
+    let mut units = 0;
+    if count == 0 {
+        return Ok(0);
+    }
+
+    match unit {
+        OffsetUnit::Byte => {
+            bytes = chunks.into_iter().map(str::len).sum();
+            units = bytes;
+            if count <= bytes {
+                return Ok(count);
+            }
+        }
+        OffsetUnit::Char | OffsetUnit::Utf16 => {
+            for chunk in chunks {
+                for c in chunk.chars() {
+                    units += char_units(c, unit);
+                    bytes += c.len_utf8();
+                    if units == count {
+                        return Ok(bytes);
+                    }
+                    //	The count falls inside a surrogate pair.
+                    if units > count {
+                        return Err(Error::InvalidBoundary(count));
+                    }
+                }
+            }
+        }
+        OffsetUnit::Grapheme => {
+            for boundary in GraphemeBoundaries::new(chunks) {
+                units += 1;
+                if units == count {
+                    return Ok(boundary);
+                }
+            }
+        }
+    }
+    Err(Error::OutOfBounds {
+        pos: count,
+        len: units,
+    })
+}
+
+//	Number of units in the chunks.
+pub fn unit_count<'a, I>(chunks: I, unit: OffsetUnit) -> usize
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    match unit {
+        OffsetUnit::Byte => chunks.into_iter().map(str::len).sum(),
+        OffsetUnit::Char => chunks.into_iter().map(|c| c.chars().count()).sum(),
+        OffsetUnit::Utf16 => chunks
+            .into_iter()
+            .map(|c| c.chars().map(char::len_utf16).sum::<usize>())
+            .sum(),
+        OffsetUnit::Grapheme => GraphemeBoundaries::new(chunks).count(),
+    }
+}
+
+fn char_units(c: char, unit: OffsetUnit) -> usize {
+    match unit {
+        OffsetUnit::Utf16 => c.len_utf16(),
+        _ => 1,
+    }
+}
+
+//	Byte offsets at the end of each grapheme cluster in a chunked text.
+//	Clusters may span chunks.
+struct GraphemeBoundaries<'a> {
+    //	Start position and text of each non empty chunk.
+    chunks: Vec<(usize, &'a str)>,
+    index: usize,
+    cursor: GraphemeCursor,
+}
+
+impl<'a> GraphemeBoundaries<'a> {
+    fn new<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut len = 0;
+        let chunks: Vec<(usize, &str)> = chunks
+            .into_iter()
+            .filter(|c| !c.is_empty())
+            .map(|c| {
+                len += c.len();
+                (len - c.len(), c)
+            })
+            .collect();
+        Self {
+            chunks,
+            index: 0,
+            cursor: GraphemeCursor::new(0, len, true),
+        }
+    }
+}
+
+impl Iterator for GraphemeBoundaries<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let &(start, chunk) = self.chunks.get(self.index)?;
+            match self.cursor.next_boundary(chunk, start) {
+                Ok(boundary) => return boundary,
+                Err(GraphemeIncomplete::NextChunk) => self.index += 1,
+                Err(GraphemeIncomplete::PreContext(end)) => {
+                    //	Provide the chunk that ends where the context is needed.
+                    let &(start, chunk) = self.chunks.iter().find(|(s, c)| s + c.len() == end)?;
+                    self.cursor.provide_context(chunk, start);
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+}
@@ -1,6 +1,47 @@
-use common::piecetable::PieceTable;
+use std::{
+    env,
+    fs::File,
+    io::{self, IsTerminal},
+    process,
+};
+
+use common::{piecetable::PieceTable, piecetree::PieceTree, TextBuffer};
+use flate2::read::GzDecoder;
+
+//	Creates the text buffer backend named on the command line.
+fn new_buffer(backend: &str, table: PieceTable) -> Box<dyn TextBuffer> {
+    match backend {
+        "tree" => Box::new(PieceTree::new(&table.get_text(None, None))),
+        _ => Box::new(table),
+    }
+}
+
+//	Loads the file named on the command line. Files ending in `.gz` are
+//	decompressed while they are read, and `-` or piped input is read
+//	from stdin.
+fn load(path: Option<&str>) -> common::Result<PieceTable> {
+    match path {
+        Some("-") => PieceTable::from_reader(io::stdin().lock()),
+        Some(path) if path.ends_with(".gz") => {
+            PieceTable::from_reader(GzDecoder::new(File::open(path)?))
+        }
+        Some(path) => PieceTable::from_file(path),
+        None if !io::stdin().is_terminal() => PieceTable::from_reader(io::stdin().lock()),
+        None => Ok(PieceTable::new("Hello World!")),
+    }
+}
 
 fn main() {
-    let pt = PieceTable::new("Hello World!");
+    let mut args = env::args().skip(1);
+    let backend = args.next().unwrap_or_default();
+    let path = args.next();
+    let table = match load(path.as_deref()) {
+        Ok(table) => table,
+        Err(err) => {
+            eprintln!("trext-rs: {err}");
+            process::exit(1);
+        }
+    };
+    let pt = new_buffer(&backend, table);
     println!("{}", pt.get_text(None, None));
 }